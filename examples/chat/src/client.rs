@@ -1,10 +1,10 @@
 use std::{io::Write, time::{Duration, Instant}};
 
-use client_server::{Client, ClientEvent, transport::ClientTransport};
-use crate::{ClientToServerMsg, ServerToClientMsg, spawn_press_enter_to_quit_thread};
+use client_server::{ClientEvent, TypedClient, transport::ClientTransport};
+use crate::{CHAT_CHANNEL, ClientToServerMsg, ServerToClientMsg, spawn_press_enter_to_quit_thread};
 
 pub fn run(client_transport: Box<dyn ClientTransport>) {
-	let mut client = Client::new(client_transport);
+	let mut client = TypedClient::<ServerToClientMsg, ClientToServerMsg>::new(client_transport);
 
 	println!("Successfully connected to server!");
 	println!("When the server reads our msg, it sends back a notification: '✅'");
@@ -18,19 +18,27 @@ pub fn run(client_transport: Box<dyn ClientTransport>) {
 			break;
 		}
 
-		while let Some(event) = client.handle_event() {
+		// sleeps until either an event arrives or the next msg is due
+		while let Some(event) = client.wait_event(next_sent_msg_time.saturating_duration_since(Instant::now())) {
 			match event {
-				ClientEvent::ServerDisconnected => {
-					println!("\nServer disconnected");
+				ClientEvent::ServerDisconnected(reason) => {
+					println!("\nServer disconnected: {reason}");
+					return;
+				},
+				ClientEvent::ConnectionRejected(reason) => {
+					println!("\nServer rejected the connection: {reason}");
 					return;
 				},
 				ClientEvent::FailedToParseMsg(e) => eprintln!("Faield to parse server msg: {e}"),
 				ClientEvent::FailedToReceiveMsg(e) => eprintln!("Failed to receive server msg: {e}"),
-				ClientEvent::MsgFromServer(msg) => {
-					match msg {
+				// the server only ever responds to our requests
+				ClientEvent::MsgFromServer(_server_msg) => {},
+				ClientEvent::Response(_request_id, server_msg) => {
+					match server_msg {
 						ServerToClientMsg::TextMsgReceived => println!(" ✅"),
 					}
-				}
+				},
+				ClientEvent::RequestFailed(_request_id, e) => println!(" ❌ {e}"),
 			}
 		}
 
@@ -38,7 +46,9 @@ pub fn run(client_transport: Box<dyn ClientTransport>) {
 			print!("Sending \"Hello, World!\" to the server...");
 			std::io::stdout().flush().unwrap();
 			let hello_world_msg = ClientToServerMsg::TextMsg("Hello, World!".to_string());
-			client.send(&hello_world_msg);
+			if let Err(e) = client.request(&hello_world_msg, CHAT_CHANNEL, Duration::from_secs(1)) {
+				eprintln!("Failed to send msg: {e}");
+			}
 			next_sent_msg_time = Instant::now() + Duration::from_secs(1);
 		}
 	}
@@ -1,9 +1,12 @@
 use serde::{Deserialize, Serialize};
+use client_server::ChannelId;
 
 pub mod client;
 pub mod server;
 
 pub const PORT: u16 = 5000;
+// the default channel of every transport, which is reliable and ordered
+pub const CHAT_CHANNEL: ChannelId = 0;
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientToServerMsg {
@@ -1,8 +1,14 @@
-use client_server::{Server, ServerEvent, transport::ServerTransport};
+use std::time::Duration;
+use client_server::{BincodeCodec, ServerConfig, ServerEvent, TypedServer, transport::ServerTransport};
 use crate::{ClientToServerMsg, ServerToClientMsg, spawn_press_enter_to_quit_thread};
 
 pub fn run(server_transport: Box<dyn ServerTransport>) {
-	let mut server = Server::new(server_transport);
+	let config = ServerConfig {
+		max_clients: Some(64),
+		max_msgs_per_second: Some(20),
+		..ServerConfig::default()
+	};
+	let mut server = TypedServer::<ClientToServerMsg, ServerToClientMsg>::with_config(server_transport, BincodeCodec, config);
 
 	let exit_input_receiver = spawn_press_enter_to_quit_thread();
 
@@ -11,19 +17,28 @@ pub fn run(server_transport: Box<dyn ServerTransport>) {
 			break;
 		}
 
-		while let Some(event) = server.receive_event::<ClientToServerMsg>() {
+		// wakes up regularly to check whether we should quit
+		while let Some(event) = server.wait_event(Duration::from_millis(100)) {
 			match event {
 				ServerEvent::NewClient(client_id) => println!("New client connected: {client_id}"),
-				ServerEvent::ClientDisconnected(client_id) => println!("Client disconnected: {client_id}"),
+				ServerEvent::ClientDisconnected(client_id, reason) => println!("Client disconnected: {client_id}: {reason}"),
 				ServerEvent::FailedToParseMsg(client_id) => eprintln!("Failed to parse msg from {client_id}"),
 				ServerEvent::FailedToAcceptConnection(e) => eprintln!("Failed to accept connection: {e}"),
 				ServerEvent::FailedToReceiveMsg(e) => eprintln!("Failed to receive msg: {e}"),
+				ServerEvent::ClientRejected(address, reason) => println!("Rejected client {address}: {reason}"),
 
 				ServerEvent::NewMsg(client_msg) => {
 					match client_msg.msg {
+						ClientToServerMsg::TextMsg(text) => println!("New msg from {}: {}", client_msg.client_id, text),
+					}
+				},
+				ServerEvent::Request { client_id, request_id, msg } => {
+					match msg {
 						ClientToServerMsg::TextMsg(text) => {
-							println!("New msg from {}: {}", client_msg.client_id, text);
-							server.send_to(client_msg.client_id, &ServerToClientMsg::TextMsgReceived)
+							println!("New msg from {client_id}: {text}");
+							if let Err(e) = server.respond(request_id, &ServerToClientMsg::TextMsgReceived) {
+								eprintln!("Failed to respond to {client_id}: {e}");
+							}
 						},
 					}
 				},
@@ -1,30 +1,30 @@
 use std::{collections::HashMap, time::Instant};
 use client_server::{Server, ServerEvent, transport::udp::UdpServerTransport};
-use simulator::{PORT, ClientToServerMsg};
+use simulator::{PORT, ClientToServerMsg, udp_config};
 
 fn main() {
 	println!("Running as server!");
-	let server_transport = UdpServerTransport::bind_port(PORT).unwrap();
+	let server_transport = UdpServerTransport::with_config(("0.0.0.0", PORT), udp_config()).unwrap();
 	let mut server = Server::new(Box::new(server_transport));
 
 	let mut client_last_packet_time = HashMap::new();
 
-	loop {
-		while let Some(event) = server.receive_event::<ClientToServerMsg>() {
-			match event {
-				ServerEvent::NewClient(client_id) => println!("New client connected: {client_id}"),
-				ServerEvent::ClientDisconnected(client_id) => println!("Client disconnected: {client_id}"),
-				ServerEvent::FailedToParseMsg(client_id) => eprintln!("Failed to parse msg from {client_id}"),
-				ServerEvent::FailedToAcceptConnection(e) => eprintln!("Failed to accept connection: {e}"),
-				ServerEvent::FailedToReceiveMsg(e) => eprintln!("Failed to receive msg: {e}"),
+	for event in server.events::<ClientToServerMsg>() {
+		match event {
+			ServerEvent::NewClient(client_id) => println!("New client connected: {client_id}"),
+			ServerEvent::ClientDisconnected(client_id, reason) => println!("Client disconnected: {client_id}: {reason}"),
+			ServerEvent::FailedToParseMsg(client_id) => eprintln!("Failed to parse msg from {client_id}"),
+			ServerEvent::FailedToAcceptConnection(e) => eprintln!("Failed to accept connection: {e}"),
+			ServerEvent::FailedToReceiveMsg(e) => eprintln!("Failed to receive msg: {e}"),
+			ServerEvent::ClientRejected(address, reason) => println!("Rejected client {address}: {reason}"),
+			ServerEvent::Request { client_id, .. } => eprintln!("Unexpected request from {client_id}"),
 
-				ServerEvent::NewMsg(client_msg) => {
-					let last_packet_time = client_last_packet_time.entry(client_msg.client_id).or_insert(Instant::now());
-					let duration = (Instant::now() - *last_packet_time).as_secs_f32();
-					println!("{}: {} packets/s", client_msg.client_id, 1.0 / duration);
-					*last_packet_time = Instant::now();
-				},
-			}
+			ServerEvent::NewMsg(client_msg) => {
+				let last_packet_time = client_last_packet_time.entry(client_msg.client_id).or_insert(Instant::now());
+				let duration = (Instant::now() - *last_packet_time).as_secs_f32();
+				println!("{}: {} packets/s", client_msg.client_id, 1.0 / duration);
+				*last_packet_time = Instant::now();
+			},
 		}
 	}
 }
@@ -1,8 +1,18 @@
 use serde::{Deserialize, Serialize};
+use client_server::{ChannelId, DeliveryMode, transport::udp::UdpConfig};
 
 
 
 pub const PORT: u16 = 6000;
+pub const HELLO_CHANNEL: ChannelId = 0;
+
+// the simulator drops whole msgs on top of udp, so reliable channels wouldn't resend them either
+pub fn udp_config() -> UdpConfig {
+	UdpConfig {
+		channels: vec![DeliveryMode::Unreliable],
+		..Default::default()
+	}
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct ClientToServerMsg {
@@ -16,3 +26,9 @@ impl ClientToServerMsg {
 		}
 	}
 }
+
+impl Default for ClientToServerMsg {
+	fn default() -> Self {
+		Self::new()
+	}
+}
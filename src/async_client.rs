@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use futures_core::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Sleep;
+use crate::client::{Client, ClientEvent};
+use crate::codec::{BincodeCodec, Codec};
+use crate::rpc::{MsgKind, PendingRequests, RequestError, RequestId, encode_body, encode_msg};
+use crate::transport::asynchronous::AsyncClientTransport;
+use crate::transport::{ChannelId, SendError};
+
+pub struct AsyncClient<C: Codec = BincodeCodec> {
+	transport: Box<dyn AsyncClientTransport>,
+	codec: C,
+	pending_requests: PendingRequests,
+	// wakes up the event polling once the next request times out, created lazily to not need a runtime in new
+	request_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncClient {
+	pub fn new(transport: Box<dyn AsyncClientTransport>) -> Self {
+		Self::with_codec(transport, BincodeCodec)
+	}
+}
+
+impl<C: Codec> AsyncClient<C> {
+	pub fn with_codec(transport: Box<dyn AsyncClientTransport>, codec: C) -> Self {
+		Self {
+			transport,
+			codec,
+			pending_requests: PendingRequests::default(),
+			request_timer: None,
+		}
+	}
+
+	// sends the payload a server with an authenticator admits the client with, e.g. a login token
+	// NOTE: It goes out on channel 0, transports that may lose its msgs are refused since it isn't resent
+	pub async fn with_connect_payload(mut transport: Box<dyn AsyncClientTransport>, codec: C, connect_payload: impl Into<Vec<u8>>) -> Result<Self, SendError> {
+		if !transport.is_reliable(0) {
+			return Err(SendError::UnreliableChannel(0));
+		}
+		let data = encode_body(MsgKind::Connect, &connect_payload.into());
+		transport.send(&data, 0).await?;
+		Ok(Self::with_codec(transport, codec))
+	}
+
+	pub fn poll_event<Msg: DeserializeOwned>(&mut self, cx: &mut Context<'_>) -> Poll<Option<ClientEvent<Msg>>> {
+		loop {
+			self.pending_requests.expire(Instant::now());
+			if let Some(event) = self.pending_requests.pop_failed() {
+				return Poll::Ready(Some(event));
+			}
+
+			match self.transport.poll_event(cx) {
+				Poll::Ready(Some(event)) => {
+					if let Some(event) = Client::parse_transport_event(event, &self.codec, &mut self.pending_requests) {
+						return Poll::Ready(Some(event));
+					}
+				},
+				Poll::Ready(None) => {
+					// no response can arrive anymore
+					if !self.pending_requests.fail_all(RequestError::Disconnected) {
+						return Poll::Ready(None);
+					}
+				},
+				Poll::Pending => {
+					let Some(deadline) = self.pending_requests.next_deadline() else {
+						return Poll::Pending;
+					};
+					let request_timer = self.request_timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline.into())));
+					request_timer.as_mut().reset(deadline.into());
+					if request_timer.as_mut().poll(cx).is_pending() {
+						return Poll::Pending;
+					}
+				},
+			}
+		}
+	}
+
+	// None once the transport shut down
+	pub async fn next_event<Msg: DeserializeOwned>(&mut self) -> Option<ClientEvent<Msg>> {
+		std::future::poll_fn(|cx| self.poll_event(cx)).await
+	}
+
+	pub fn events<Msg: DeserializeOwned>(&mut self) -> ClientEventStream<'_, Msg, C> {
+		ClientEventStream {
+			client: self,
+			msg: PhantomData,
+		}
+	}
+
+	pub async fn send<T: Serialize>(&mut self, msg: &T, channel_id: ChannelId) -> Result<(), SendError> {
+		let data = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send(&data, channel_id).await
+	}
+
+	// The response arrives as a Response event with the returned id, unless the request fails first.
+	// NOTE: Requests on unreliable channels may get lost, which only shows up as a timeout
+	pub async fn request<T: Serialize>(&mut self, msg: &T, channel_id: ChannelId, timeout: Duration) -> Result<RequestId, SendError> {
+		let request_id = self.pending_requests.next_id();
+		let data = encode_msg(MsgKind::Request(request_id), msg, &self.codec)?;
+		self.transport.send(&data, channel_id).await?;
+		self.pending_requests.insert(request_id, Instant::now() + timeout);
+		Ok(request_id)
+	}
+
+	// sends the msgs a coalescing transport queued, e.g. once at the end of every tick
+	pub async fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush().await
+	}
+}
+
+pub struct ClientEventStream<'a, Msg, C: Codec = BincodeCodec> {
+	client: &'a mut AsyncClient<C>,
+	msg: PhantomData<fn() -> Msg>,
+}
+
+impl<Msg: DeserializeOwned, C: Codec> Stream for ClientEventStream<'_, Msg, C> {
+	type Item = ClientEvent<Msg>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		self.get_mut().client.poll_event(cx)
+	}
+}
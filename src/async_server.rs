@@ -0,0 +1,188 @@
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures_core::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Sleep;
+use crate::auth::Authenticator;
+use crate::codec::{BincodeCodec, Codec};
+use crate::rpc::{ClientRequestId, MsgKind, encode_msg};
+use crate::server::{ClientId, ServerEvent};
+use crate::server_impl::ClientRegistry;
+use crate::transport::asynchronous::{AsyncServerTransport, BoxFuture};
+use crate::transport::{ChannelId, DisconnectReason, SendError, ServerConfig, ServerTransportEvent};
+
+pub struct AsyncServer<C: Codec = BincodeCodec> {
+	transport: Box<dyn AsyncServerTransport>,
+	clients: ClientRegistry,
+	codec: C,
+	// wakes up the event polling once the next pending client times out, created lazily to not need a runtime in new
+	pending_timer: Option<Pin<Box<Sleep>>>,
+	// the notifications of the clients the registry rejected or kicked, driven by every poll
+	disconnects: Vec<BoxFuture<'static, ()>>,
+}
+
+impl AsyncServer {
+	pub fn new(transport: Box<dyn AsyncServerTransport>) -> Self {
+		Self::with_codec(transport, BincodeCodec)
+	}
+}
+
+impl<C: Codec> AsyncServer<C> {
+	pub fn with_codec(transport: Box<dyn AsyncServerTransport>, codec: C) -> Self {
+		Self::with_config(transport, codec, ServerConfig::default())
+	}
+
+	pub fn with_config(mut transport: Box<dyn AsyncServerTransport>, codec: C, config: ServerConfig) -> Self {
+		transport.apply_config(&config);
+		Self {
+			transport,
+			clients: ClientRegistry::new(config),
+			codec,
+			pending_timer: None,
+			disconnects: Vec::new(),
+		}
+	}
+
+	// clients only get admitted once their connect payload got accepted, before that they don't show up as NewClient
+	pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+		self.clients.set_authenticator(authenticator);
+		self
+	}
+
+	pub fn client_address(&self, client_id: ClientId) -> Option<SocketAddr> {
+		self.clients.address(client_id)
+	}
+
+	// the identity the authenticator admitted the client with
+	pub fn client_identity(&self, client_id: ClientId) -> Option<&str> {
+		self.clients.identity(client_id)
+	}
+
+	// all connected clients, ordered by when they connected
+	pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+		self.clients.ids()
+	}
+
+	pub async fn send_to<Msg: Serialize>(&mut self, client_id: ClientId, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let address = self.clients.address(client_id).ok_or(SendError::Disconnected)?;
+		let bytes = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send(address, &bytes, channel_id).await
+	}
+
+	// unknown client ids are skipped
+	pub async fn send_to_many<Msg: Serialize>(&mut self, client_ids: impl IntoIterator<Item = ClientId>, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_of(client_ids);
+		self.send_to_addresses(&addresses, msg, channel_id).await
+	}
+
+	pub async fn broadcast<Msg: Serialize>(&mut self, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_except(None);
+		self.send_to_addresses(&addresses, msg, channel_id).await
+	}
+
+	pub async fn broadcast_except<Msg: Serialize>(&mut self, except_client_id: ClientId, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_except(Some(except_client_id));
+		self.send_to_addresses(&addresses, msg, channel_id).await
+	}
+
+	async fn send_to_addresses<Msg: Serialize>(&mut self, addresses: &[SocketAddr], msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		if addresses.is_empty() {
+			return Ok(());
+		}
+		let bytes = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send_to_many(addresses, &bytes, channel_id).await
+	}
+
+	// sends the msgs a coalescing transport queued, e.g. once at the end of every tick
+	pub async fn flush(&mut self) -> Result<(), SendError> {
+		for disconnect in self.disconnects.drain(..) {
+			disconnect.await;
+		}
+		self.transport.flush().await
+	}
+
+	// the response goes back on the channel the request arrived on
+	pub async fn respond<Msg: Serialize>(&mut self, request_id: ClientRequestId, msg: &Msg) -> Result<(), SendError> {
+		let address = self.clients.address(request_id.client_id).ok_or(SendError::Disconnected)?;
+		let data = encode_msg(MsgKind::Response(request_id.request_id), msg, &self.codec)?;
+		self.transport.send(address, &data, request_id.channel_id).await
+	}
+
+	// the kicked client sees the reason in its ServerDisconnected event
+	pub async fn disconnect(&mut self, client_id: ClientId, reason: impl Into<String>) {
+		if let Some(address) = self.clients.address(client_id) {
+			self.clients.unregister(address);
+			self.transport.disconnect(address, DisconnectReason::Kicked(reason.into())).await;
+		}
+	}
+
+	pub fn poll_event<Msg: DeserializeOwned>(&mut self, cx: &mut Context<'_>) -> Poll<Option<ServerEvent<Msg>>> {
+		loop {
+			self.disconnects.retain_mut(|disconnect| disconnect.as_mut().poll(cx).is_pending());
+			if let Some(event) = self.clients.pop_queued_event() {
+				if let Some(event) = self.handle_transport_event(event, cx) {
+					return Poll::Ready(Some(event));
+				}
+				continue;
+			}
+			match self.transport.poll_event(cx) {
+				Poll::Ready(Some(event)) => {
+					if let Some(event) = self.handle_transport_event(event, cx) {
+						return Poll::Ready(Some(event));
+					}
+				},
+				Poll::Ready(None) => return Poll::Ready(None),
+				Poll::Pending => {
+					let Some(deadline) = self.clients.next_pending_deadline() else {
+						return Poll::Pending;
+					};
+					let pending_timer = self.pending_timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline.into())));
+					pending_timer.as_mut().reset(deadline.into());
+					if pending_timer.as_mut().poll(cx).is_pending() {
+						return Poll::Pending;
+					}
+				},
+			}
+		}
+	}
+
+	// None once the transport shut down
+	pub async fn next_event<Msg: DeserializeOwned>(&mut self) -> Option<ServerEvent<Msg>> {
+		std::future::poll_fn(|cx| self.poll_event(cx)).await
+	}
+
+	// the clients the registry rejected or kicked are gone before their event is returned, their notifications may still be on the way
+	fn handle_transport_event<Msg: DeserializeOwned>(&mut self, event: ServerTransportEvent, cx: &mut Context<'_>) -> Option<ServerEvent<Msg>> {
+		let event = self.clients.handle_transport_event(event, &self.codec);
+		for (address, reason) in self.clients.take_disconnects() {
+			let mut disconnect = self.transport.disconnect(address, reason);
+			if disconnect.as_mut().poll(cx).is_pending() {
+				self.disconnects.push(disconnect);
+			}
+		}
+		event
+	}
+
+	pub fn events<Msg: DeserializeOwned>(&mut self) -> ServerEventStream<'_, Msg, C> {
+		ServerEventStream {
+			server: self,
+			msg: PhantomData,
+		}
+	}
+}
+
+pub struct ServerEventStream<'a, Msg, C: Codec = BincodeCodec> {
+	server: &'a mut AsyncServer<C>,
+	msg: PhantomData<fn() -> Msg>,
+}
+
+impl<Msg: DeserializeOwned, C: Codec> Stream for ServerEventStream<'_, Msg, C> {
+	type Item = ServerEvent<Msg>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		self.get_mut().server.poll_event(cx)
+	}
+}
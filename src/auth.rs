@@ -0,0 +1,13 @@
+use std::net::SocketAddr;
+
+// Decides which clients get admitted, based on the payload they connect with, e.g. a login token.
+// The identity gets attached to the admitted client, the error is the reason the client gets rejected with.
+pub trait Authenticator: Send {
+	fn authenticate(&mut self, address: SocketAddr, connect_payload: &[u8]) -> Result<String, String>;
+}
+
+impl<F: FnMut(SocketAddr, &[u8]) -> Result<String, String> + Send> Authenticator for F {
+	fn authenticate(&mut self, address: SocketAddr, connect_payload: &[u8]) -> Result<String, String> {
+		self(address, connect_payload)
+	}
+}
@@ -1,45 +1,173 @@
-use crate::transport::{ClientTransport, ClientTransportEvent};
+use crate::codec::{BincodeCodec, Codec, CodecError};
+use crate::rpc::{MsgKind, PendingRequests, RequestError, RequestId, decode_msg, encode_body, encode_msg};
+use crate::transport::{ChannelId, ClientTransport, ClientTransportEvent, ConnectionRejectedReason, DisconnectReason, SendError};
 use serde::{de::DeserializeOwned, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct ServerMsg<Msg> {
+	pub channel_id: ChannelId,
+	pub msg: Msg,
+}
 
 pub enum ClientEvent<Msg> {
-	MsgFromServer(Msg),
+	MsgFromServer(ServerMsg<Msg>),
+	// the response of the server to one of our requests
+	Response(RequestId, Msg),
+	RequestFailed(RequestId, RequestError),
 	FailedToReceiveMsg(std::io::Error),
-	FailedToParseMsg(Box<bincode::ErrorKind>),
-	ServerDisconnected,
+	FailedToParseMsg(CodecError),
+	ConnectionRejected(ConnectionRejectedReason),
+	ServerDisconnected(DisconnectReason),
 }
 
-pub struct Client {
+pub struct Client<C: Codec = BincodeCodec> {
 	transport: Box<dyn ClientTransport>,
+	codec: C,
+	pending_requests: PendingRequests,
 }
 
 impl Client {
 	pub fn new(stream_transport: Box<dyn ClientTransport>) -> Self {
+		Self::with_codec(stream_transport, BincodeCodec)
+	}
+}
+
+impl<C: Codec> Client<C> {
+	pub fn with_codec(transport: Box<dyn ClientTransport>, codec: C) -> Self {
 		Self {
-			transport: stream_transport,
+			transport,
+			codec,
+			pending_requests: PendingRequests::default(),
 		}
 	}
 
+	// sends the payload a server with an authenticator admits the client with, e.g. a login token
+	// NOTE: It goes out on channel 0, transports that may lose its msgs are refused since it isn't resent
+	pub fn with_connect_payload(mut transport: Box<dyn ClientTransport>, codec: C, connect_payload: impl Into<Vec<u8>>) -> Result<Self, SendError> {
+		if !transport.is_reliable(0) {
+			return Err(SendError::UnreliableChannel(0));
+		}
+		let data = encode_body(MsgKind::Connect, &connect_payload.into());
+		transport.send(&data, 0)?;
+		Ok(Self::with_codec(transport, codec))
+	}
+
 	pub fn handle_event<Msg: DeserializeOwned>(&mut self) -> Option<ClientEvent<Msg>> {
-		if let Some(event) = self.transport.receive_event() {
-			match event {
-				ClientTransportEvent::ServerDisconnected => Some(ClientEvent::ServerDisconnected),
-				ClientTransportEvent::FailedToReceiveMsg(e) => Some(ClientEvent::FailedToReceiveMsg(e)),
-				ClientTransportEvent::NewMsg(data) => {
-					match bincode::deserialize(&data) {
-						Ok(msg) => Some(ClientEvent::MsgFromServer(msg)),
-						Err(e) => Some(ClientEvent::FailedToParseMsg(e)),
-					}
-				}
+		loop {
+			self.pending_requests.expire(Instant::now());
+			if let Some(event) = self.pending_requests.pop_failed() {
+				return Some(event);
+			}
+
+			let event = self.transport.receive_event()?;
+			if let Some(event) = Self::parse_transport_event(event, &self.codec, &mut self.pending_requests) {
+				return Some(event);
 			}
 		}
-		else {
-			None
+	}
+
+	// parks the caller until an event arrives or the timeout runs out
+	pub fn wait_event<Msg: DeserializeOwned>(&mut self, timeout: Duration) -> Option<ClientEvent<Msg>> {
+		self.next_event(Some(Instant::now() + timeout))
+	}
+
+	// blocks on every event, ends once the transport shut down
+	pub fn events<Msg: DeserializeOwned>(&mut self) -> impl Iterator<Item = ClientEvent<Msg>> + '_ {
+		std::iter::from_fn(|| self.next_event(None))
+	}
+
+	// None once the deadline passed or the transport shut down
+	fn next_event<Msg: DeserializeOwned>(&mut self, deadline: Option<Instant>) -> Option<ClientEvent<Msg>> {
+		loop {
+			let now = Instant::now();
+			self.pending_requests.expire(now);
+			if let Some(event) = self.pending_requests.pop_failed() {
+				return Some(event);
+			}
+
+			// also wakes up for the next request that times out
+			let wake_up = deadline.into_iter().chain(self.pending_requests.next_deadline()).min();
+			match self.transport.wait_event(wake_up.map(|wake_up| wake_up.saturating_duration_since(now))) {
+				Some(event) => {
+					if let Some(event) = Self::parse_transport_event(event, &self.codec, &mut self.pending_requests) {
+						return Some(event);
+					}
+				},
+				None => {
+					let now = Instant::now();
+					if deadline.is_some_and(|deadline| deadline <= now) {
+						return None;
+					}
+					// returning early means the transport shut down, so no response can arrive anymore
+					let shut_down = wake_up.is_none_or(|wake_up| now < wake_up);
+					if shut_down && !self.pending_requests.fail_all(RequestError::Disconnected) {
+						return None;
+					}
+				},
+			}
 		}
 	}
 
-	pub fn send<T: Serialize>(&mut self, msg: &T) {
-		if let Ok(data) = bincode::serialize(msg) {
-			self.transport.send(&data);
+	// returns None for responses nobody waits for anymore
+	pub(crate) fn parse_transport_event<Msg: DeserializeOwned>(event: ClientTransportEvent, codec: &C, pending_requests: &mut PendingRequests) -> Option<ClientEvent<Msg>> {
+		match event {
+			// the client never got admitted, so from its view the connection got rejected
+			ClientTransportEvent::ServerDisconnected(DisconnectReason::Rejected(reason)) => {
+				pending_requests.fail_all(RequestError::Disconnected);
+				Some(ClientEvent::ConnectionRejected(reason))
+			},
+			ClientTransportEvent::ServerDisconnected(reason) => {
+				pending_requests.fail_all(RequestError::Disconnected);
+				Some(ClientEvent::ServerDisconnected(reason))
+			},
+			ClientTransportEvent::FailedToReceiveMsg(e) => Some(ClientEvent::FailedToReceiveMsg(e)),
+			ClientTransportEvent::ConnectionRejected(reason) => {
+				pending_requests.fail_all(RequestError::Disconnected);
+				Some(ClientEvent::ConnectionRejected(reason))
+			},
+			ClientTransportEvent::NewMsg(channel_id, data) => {
+				let Some((kind, body)) = decode_msg(&data) else {
+					return Some(ClientEvent::FailedToParseMsg(CodecError::new("msg without a valid kind prefix")));
+				};
+				if matches!(kind, MsgKind::Connect) {
+					return Some(ClientEvent::FailedToParseMsg(CodecError::new("servers can't send connect payloads")));
+				}
+				let msg = match codec.deserialize(body) {
+					Ok(msg) => msg,
+					Err(e) => return Some(ClientEvent::FailedToParseMsg(e)),
+				};
+
+				match kind {
+					MsgKind::Msg => Some(ClientEvent::MsgFromServer(ServerMsg {
+						channel_id,
+						msg,
+					})),
+					MsgKind::Response(request_id) => pending_requests.complete(request_id).then_some(ClientEvent::Response(request_id, msg)),
+					MsgKind::Request(_) => Some(ClientEvent::FailedToParseMsg(CodecError::new("servers can't send requests"))),
+					MsgKind::Connect => unreachable!(),
+				}
+			}
 		}
 	}
+
+	pub fn send<T: Serialize>(&mut self, msg: &T, channel_id: ChannelId) -> Result<(), SendError> {
+		let data = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send(&data, channel_id)
+	}
+
+	// The response arrives as a Response event with the returned id, unless the request fails first.
+	// NOTE: Requests on unreliable channels may get lost, which only shows up as a timeout
+	pub fn request<T: Serialize>(&mut self, msg: &T, channel_id: ChannelId, timeout: Duration) -> Result<RequestId, SendError> {
+		let request_id = self.pending_requests.next_id();
+		let data = encode_msg(MsgKind::Request(request_id), msg, &self.codec)?;
+		self.transport.send(&data, channel_id)?;
+		self.pending_requests.insert(request_id, Instant::now() + timeout);
+		Ok(request_id)
+	}
+
+	// sends the msgs a coalescing transport queued, e.g. once at the end of every tick
+	pub fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
 }
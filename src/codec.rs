@@ -0,0 +1,95 @@
+use std::error::Error;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Turns msgs into bytes and back. Client and server have to use the same codec.
+// NOTE: Client and Server prefix every encoded msg with a kind byte (see rpc.rs), so the bytes on the wire are
+// [0] + msg for plain msgs, [1] + request id + msg for requests, [2] + request id + msg for responses and
+// [3] + payload for connect payloads. Request ids are little endian u64s. Peers that don't use this crate
+// have to add and strip the prefix themselves, e.g. web tooling sends a 0 byte followed by the json of a msg.
+pub trait Codec {
+	fn serialize<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError>;
+	fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CodecError>;
+}
+
+// wraps the error of whatever format the codec uses, so that it doesn't leak into the api
+#[derive(Debug)]
+pub struct CodecError(Box<dyn Error + Send + Sync>);
+
+impl CodecError {
+	pub fn new(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+		Self(error.into())
+	}
+}
+
+impl std::fmt::Display for CodecError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl Error for CodecError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		self.0.source()
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+	fn serialize<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+		bincode::serialize(msg).map_err(CodecError::new)
+	}
+
+	fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CodecError> {
+		bincode::deserialize(data).map_err(CodecError::new)
+	}
+}
+
+// readable by pretty much everything, e.g. web tooling, but also the largest encoding
+// NOTE: The kind prefix of every msg isn't json, see Codec
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+	fn serialize<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+		serde_json::to_vec(msg).map_err(CodecError::new)
+	}
+
+	fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CodecError> {
+		serde_json::from_slice(data).map_err(CodecError::new)
+	}
+}
+
+// NOTE: Structs are encoded as maps with their field names, so that other msgpack implementations can read them
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+	fn serialize<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+		rmp_serde::to_vec_named(msg).map_err(CodecError::new)
+	}
+
+	fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CodecError> {
+		rmp_serde::from_slice(data).map_err(CodecError::new)
+	}
+}
+
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+	fn serialize<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, CodecError> {
+		postcard::to_allocvec(msg).map_err(CodecError::new)
+	}
+
+	fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, CodecError> {
+		postcard::from_bytes(data).map_err(CodecError::new)
+	}
+}
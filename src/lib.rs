@@ -1,7 +1,26 @@
+mod auth;
 mod client;
 mod server;
 mod server_impl;
+mod rpc;
+mod typed_client;
+mod typed_server;
+#[cfg(feature = "tokio")]
+mod async_client;
+#[cfg(feature = "tokio")]
+mod async_server;
 pub mod transport;
+pub mod codec;
 
-pub use client::{Client, ClientEvent};
-pub use server::{Server, ClientId, ServerEvent};
+pub use auth::Authenticator;
+pub use client::{Client, ClientEvent, ServerMsg};
+pub use server::{Server, ClientId, ClientMsg, ServerEvent};
+pub use typed_client::TypedClient;
+pub use typed_server::TypedServer;
+pub use codec::{BincodeCodec, Codec, CodecError};
+pub use rpc::{ClientRequestId, RequestError, RequestId};
+pub use transport::{ChannelId, ConnectionRejectedReason, DeliveryMode, DisconnectReason, SendError, ServerConfig};
+#[cfg(feature = "tokio")]
+pub use async_client::{AsyncClient, ClientEventStream};
+#[cfg(feature = "tokio")]
+pub use async_server::{AsyncServer, ServerEventStream};
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::time::Instant;
+use serde::Serialize;
+use crate::client::ClientEvent;
+use crate::codec::Codec;
+use crate::server::ClientId;
+use crate::transport::{ChannelId, SendError};
+
+// NOTE: Every msg is prefixed with its kind, so that requests and responses can be told apart from plain msgs.
+// Requests and responses carry their id right after it. The layout is part of the wire format, see Codec.
+const MSG: u8 = 0;
+const REQUEST: u8 = 1;
+const RESPONSE: u8 = 2;
+// carries the raw connect payload of a client, which doesn't go through the codec
+const CONNECT: u8 = 3;
+const REQUEST_ID_SIZE: usize = std::mem::size_of::<u64>();
+
+// handle of a request the client sent, its response carries the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+// a request the server received, which tells the response where to go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientRequestId {
+	pub(crate) client_id: ClientId,
+	pub(crate) channel_id: ChannelId,
+	pub(crate) request_id: RequestId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+	// the server didn't respond before the timeout ran out
+	TimedOut,
+	// the connection closed before the server responded
+	Disconnected,
+}
+
+impl std::fmt::Display for RequestError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::TimedOut => write!(f, "request timed out"),
+			Self::Disconnected => write!(f, "disconnected before the response arrived"),
+		}
+	}
+}
+
+impl Error for RequestError {}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum MsgKind {
+	Msg,
+	Request(RequestId),
+	Response(RequestId),
+	Connect,
+}
+
+pub(crate) fn encode_msg<T: Serialize>(kind: MsgKind, msg: &T, codec: &impl Codec) -> Result<Vec<u8>, SendError> {
+	let body = codec.serialize(msg).map_err(SendError::FailedToSerialize)?;
+	Ok(encode_body(kind, &body))
+}
+
+pub(crate) fn encode_body(kind: MsgKind, body: &[u8]) -> Vec<u8> {
+	let mut data = Vec::with_capacity(1 + REQUEST_ID_SIZE + body.len());
+	match kind {
+		MsgKind::Msg => data.push(MSG),
+		MsgKind::Request(RequestId(id)) => {
+			data.push(REQUEST);
+			data.extend_from_slice(&id.to_le_bytes());
+		},
+		MsgKind::Response(RequestId(id)) => {
+			data.push(RESPONSE);
+			data.extend_from_slice(&id.to_le_bytes());
+		},
+		MsgKind::Connect => data.push(CONNECT),
+	}
+	data.extend_from_slice(body);
+	data
+}
+
+// returns the kind and the encoded msg, or None if the prefix is invalid
+pub(crate) fn decode_msg(data: &[u8]) -> Option<(MsgKind, &[u8])> {
+	let (kind, data) = data.split_first()?;
+	match *kind {
+		MSG => Some((MsgKind::Msg, data)),
+		REQUEST => {
+			let (request_id, body) = split_request_id(data)?;
+			Some((MsgKind::Request(request_id), body))
+		},
+		RESPONSE => {
+			let (request_id, body) = split_request_id(data)?;
+			Some((MsgKind::Response(request_id), body))
+		},
+		CONNECT => Some((MsgKind::Connect, data)),
+		_ => None,
+	}
+}
+
+fn split_request_id(data: &[u8]) -> Option<(RequestId, &[u8])> {
+	let (id, body) = data.split_first_chunk::<REQUEST_ID_SIZE>()?;
+	Some((RequestId(u64::from_le_bytes(*id)), body))
+}
+
+// the requests of a client that still wait for their response
+#[derive(Default)]
+pub(crate) struct PendingRequests {
+	next_id: u64,
+	deadlines: HashMap<RequestId, Instant>,
+	failed: VecDeque<(RequestId, RequestError)>,
+}
+
+impl PendingRequests {
+	// the request only becomes pending once it got sent successfully
+	pub(crate) fn next_id(&mut self) -> RequestId {
+		let request_id = RequestId(self.next_id);
+		self.next_id += 1;
+		request_id
+	}
+
+	pub(crate) fn insert(&mut self, request_id: RequestId, deadline: Instant) {
+		self.deadlines.insert(request_id, deadline);
+	}
+
+	// false for responses that weren't requested or arrived after their request timed out
+	pub(crate) fn complete(&mut self, request_id: RequestId) -> bool {
+		self.deadlines.remove(&request_id).is_some()
+	}
+
+	pub(crate) fn expire(&mut self, now: Instant) {
+		let mut expired: Vec<(RequestId, Instant)> = self.deadlines.iter()
+			.filter(|(_request_id, deadline)| **deadline <= now)
+			.map(|(request_id, deadline)| (*request_id, *deadline))
+			.collect();
+		expired.sort_by_key(|(_request_id, deadline)| *deadline);
+		for (request_id, _deadline) in expired {
+			self.deadlines.remove(&request_id);
+			self.failed.push_back((request_id, RequestError::TimedOut));
+		}
+	}
+
+	// returns false if there was nothing left to fail
+	pub(crate) fn fail_all(&mut self, error: RequestError) -> bool {
+		let mut request_ids: Vec<RequestId> = self.deadlines.drain().map(|(request_id, _deadline)| request_id).collect();
+		request_ids.sort_by_key(|request_id| request_id.0);
+		let failed_any = !request_ids.is_empty();
+		self.failed.extend(request_ids.into_iter().map(|request_id| (request_id, error.clone())));
+		failed_any
+	}
+
+	pub(crate) fn next_deadline(&self) -> Option<Instant> {
+		self.deadlines.values().min().copied()
+	}
+
+	pub(crate) fn pop_failed<Msg>(&mut self) -> Option<ClientEvent<Msg>> {
+		let (request_id, error) = self.failed.pop_front()?;
+		Some(ClientEvent::RequestFailed(request_id, error))
+	}
+}
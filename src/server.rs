@@ -1,74 +1,193 @@
 use std::io;
 use std::net::SocketAddr;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use serde::{de::DeserializeOwned, Serialize};
-use crate::transport::{ServerTransport, ServerTransportEvent};
+use crate::auth::Authenticator;
+use crate::codec::{BincodeCodec, Codec};
+use crate::rpc::{ClientRequestId, MsgKind, encode_msg};
+use crate::server_impl::ClientRegistry;
+use crate::transport::{ChannelId, ConnectionRejectedReason, DisconnectReason, SendError, ServerConfig, ServerTransport, ServerTransportEvent};
 
 pub type ClientId = usize;
 
 #[derive(Debug, Clone)]
 pub struct ClientMsg<Msg> {
 	pub client_id: ClientId,
+	pub channel_id: ChannelId,
 	pub msg: Msg,
 }
 
 pub enum ServerEvent<Msg> {
 	NewClient(ClientId),
-	ClientDisconnected(ClientId),
+	ClientDisconnected(ClientId, DisconnectReason),
 	NewMsg(ClientMsg<Msg>),
+	// has to be answered with Server::respond, otherwise the request times out on the client
+	Request {
+		client_id: ClientId,
+		request_id: ClientRequestId,
+		msg: Msg,
+	},
 	FailedToParseMsg(ClientId),
 	FailedToAcceptConnection(io::Error),
 	FailedToReceiveMsg(io::Error),
+	// a connection that got turned away before it became a client, e.g. because of the limits of the ServerConfig
+	ClientRejected(SocketAddr, ConnectionRejectedReason),
 }
 
-pub struct Server {
+pub struct Server<C: Codec = BincodeCodec> {
 	transport: Box<dyn ServerTransport>,
-	pub(crate) client_addresses: HashMap<ClientId, SocketAddr>,
+	clients: ClientRegistry,
+	codec: C,
 }
 
 impl Server {
 	pub fn new(transport: Box<dyn ServerTransport>) -> Self {
+		Self::with_codec(transport, BincodeCodec)
+	}
+}
+
+impl<C: Codec> Server<C> {
+	pub fn with_codec(transport: Box<dyn ServerTransport>, codec: C) -> Self {
+		Self::with_config(transport, codec, ServerConfig::default())
+	}
+
+	pub fn with_config(mut transport: Box<dyn ServerTransport>, codec: C, config: ServerConfig) -> Self {
+		transport.apply_config(&config);
 		Self {
 			transport,
-			client_addresses: HashMap::new(),
+			clients: ClientRegistry::new(config),
+			codec,
 		}
 	}
 
-	pub fn send_to<Msg: Serialize>(&mut self, client_id: ClientId, msg: &Msg) {
-		if let Some(address) = self.client_addresses.get(&client_id) {
-			if let Ok(bytes) = bincode::serialize(msg) {
-				self.transport.send(*address, &bytes);
-			}
+	// clients only get admitted once their connect payload got accepted, before that they don't show up as NewClient
+	pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+		self.clients.set_authenticator(authenticator);
+		self
+	}
+
+	pub fn client_address(&self, client_id: ClientId) -> Option<SocketAddr> {
+		self.clients.address(client_id)
+	}
+
+	// the identity the authenticator admitted the client with
+	pub fn client_identity(&self, client_id: ClientId) -> Option<&str> {
+		self.clients.identity(client_id)
+	}
+
+	// all connected clients, ordered by when they connected
+	pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+		self.clients.ids()
+	}
+
+	pub fn send_to<Msg: Serialize>(&mut self, client_id: ClientId, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let address = self.clients.address(client_id).ok_or(SendError::Disconnected)?;
+		let bytes = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send(address, &bytes, channel_id)
+	}
+
+	// unknown client ids are skipped
+	pub fn send_to_many<Msg: Serialize>(&mut self, client_ids: impl IntoIterator<Item = ClientId>, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_of(client_ids);
+		self.send_to_addresses(&addresses, msg, channel_id)
+	}
+
+	pub fn broadcast<Msg: Serialize>(&mut self, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_except(None);
+		self.send_to_addresses(&addresses, msg, channel_id)
+	}
+
+	pub fn broadcast_except<Msg: Serialize>(&mut self, except_client_id: ClientId, msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		let addresses = self.clients.addresses_except(Some(except_client_id));
+		self.send_to_addresses(&addresses, msg, channel_id)
+	}
+
+	// the msg only gets serialized once, no matter how many clients receive it
+	fn send_to_addresses<Msg: Serialize>(&mut self, addresses: &[SocketAddr], msg: &Msg, channel_id: ChannelId) -> Result<(), SendError> {
+		if addresses.is_empty() {
+			return Ok(());
+		}
+		let bytes = encode_msg(MsgKind::Msg, msg, &self.codec)?;
+		self.transport.send_to_many(addresses, &bytes, channel_id)
+	}
+
+	// sends the msgs a coalescing transport queued, e.g. once at the end of every tick
+	pub fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
+
+	// the response goes back on the channel the request arrived on
+	pub fn respond<Msg: Serialize>(&mut self, request_id: ClientRequestId, msg: &Msg) -> Result<(), SendError> {
+		let address = self.clients.address(request_id.client_id).ok_or(SendError::Disconnected)?;
+		let data = encode_msg(MsgKind::Response(request_id.request_id), msg, &self.codec)?;
+		self.transport.send(address, &data, request_id.channel_id)
+	}
+
+	// the kicked client sees the reason in its ServerDisconnected event
+	pub fn disconnect(&mut self, client_id: ClientId, reason: impl Into<String>) {
+		if let Some(address) = self.clients.address(client_id) {
+			self.clients.unregister(address);
+			self.transport.disconnect(address, DisconnectReason::Kicked(reason.into()));
 		}
 	}
 
 	pub fn receive_event<Msg: DeserializeOwned>(&mut self) -> Option<ServerEvent<Msg>> {
-		match self.transport.receive_event()? {
-			ServerTransportEvent::NewClient(address) => {
-				let client_id = Self::client_id_from_address(address);
-				self.client_addresses.insert(client_id, address);
-				Some(ServerEvent::NewClient(client_id))
-			},
-			ServerTransportEvent::ClientDisconnected(address) => {
-				let client_id = Self::client_id_from_address(address);
-				self.client_addresses.remove(&client_id);
-				Some(ServerEvent::ClientDisconnected(client_id))
-			},
-			ServerTransportEvent::NewMsg(transport_msg) => {
-				let client_id = Self::client_id_from_address(transport_msg.sender_address);
-
-				match bincode::deserialize(&transport_msg.data) {
-					Ok(msg) => {
-						Some(ServerEvent::NewMsg(ClientMsg {
-							client_id,
-							msg,
-						}))
-					},
-					Err(_e) => Some(ServerEvent::FailedToParseMsg(client_id)),
+		loop {
+			let event = match self.clients.pop_queued_event() {
+				Some(event) => event,
+				None => self.transport.receive_event()?,
+			};
+			if let Some(event) = self.handle_transport_event(event) {
+				return Some(event);
+			}
+		}
+	}
+
+	// parks the caller until an event arrives or the timeout runs out
+	pub fn wait_event<Msg: DeserializeOwned>(&mut self, timeout: Duration) -> Option<ServerEvent<Msg>> {
+		self.next_event(Some(Instant::now() + timeout))
+	}
+
+	// blocks on every event, ends once the transport shut down
+	pub fn events<Msg: DeserializeOwned>(&mut self) -> impl Iterator<Item = ServerEvent<Msg>> + '_ {
+		std::iter::from_fn(|| self.next_event(None))
+	}
+
+	// None once the deadline passed or the transport shut down
+	fn next_event<Msg: DeserializeOwned>(&mut self, deadline: Option<Instant>) -> Option<ServerEvent<Msg>> {
+		loop {
+			if let Some(event) = self.clients.pop_queued_event() {
+				if let Some(event) = self.handle_transport_event(event) {
+					return Some(event);
 				}
-			},
-			ServerTransportEvent::FailedToReceiveMsg(error) => Some(ServerEvent::FailedToReceiveMsg(error)),
-			ServerTransportEvent::FailedToAcceptConnection(error) => Some(ServerEvent::FailedToAcceptConnection(error)),
+				continue;
+			}
+
+			// also wakes up for the next pending client that times out
+			let wake_up = deadline.into_iter().chain(self.clients.next_pending_deadline()).min();
+			let now = Instant::now();
+			match self.transport.wait_event(wake_up.map(|wake_up| wake_up.saturating_duration_since(now))) {
+				Some(event) => {
+					if let Some(event) = self.handle_transport_event(event) {
+						return Some(event);
+					}
+				},
+				None => {
+					let now = Instant::now();
+					// returning early means the transport shut down
+					if deadline.is_some_and(|deadline| deadline <= now) || wake_up.is_none_or(|wake_up| now < wake_up) {
+						return None;
+					}
+				},
+			}
+		}
+	}
+
+	fn handle_transport_event<Msg: DeserializeOwned>(&mut self, event: ServerTransportEvent) -> Option<ServerEvent<Msg>> {
+		let event = self.clients.handle_transport_event(event, &self.codec);
+		for (address, reason) in self.clients.take_disconnects() {
+			self.transport.disconnect(address, reason);
 		}
+		event
 	}
 }
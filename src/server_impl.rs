@@ -1,11 +1,291 @@
-use crate::server::{Server, ClientId};
+use crate::auth::Authenticator;
+use crate::codec::Codec;
+use crate::rpc::{ClientRequestId, MsgKind, decode_msg};
+use crate::server::{ClientId, ClientMsg, ServerEvent};
+use crate::transport::{ConnectionRejectedReason, DisconnectReason, ServerConfig, ServerTransportEvent, TransportMsg};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 
-impl Server {
-	pub(crate) fn client_id_from_address(address: SocketAddr) -> ClientId {
-		let mut hasher = std::hash::DefaultHasher::new();
-		address.hash(&mut hasher);
-		hasher.finish() as ClientId
+// msgs on other channels may overtake the connect msg, they are kept until the client got admitted
+const MAX_PENDING_MSGS: usize = 64;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// what a client sent within the current window of the rate limits
+struct InboundRate {
+	window_start: Instant,
+	msgs: u32,
+	bytes: usize,
+}
+
+// a connected client that didn't send its connect payload yet
+struct PendingClient {
+	// None if the config has no handshake timeout
+	deadline: Option<Instant>,
+	msgs: Vec<TransportMsg>,
+}
+
+// Bidirectional table between client ids and addresses, shared by every server front end
+#[derive(Default)]
+pub(crate) struct ClientRegistry {
+	addresses: BTreeMap<ClientId, SocketAddr>,
+	ids: HashMap<SocketAddr, ClientId>,
+	next_client_id: ClientId,
+	identities: HashMap<ClientId, String>,
+	// without one every client is admitted right away
+	authenticator: Option<Box<dyn Authenticator>>,
+	pending: HashMap<SocketAddr, PendingClient>,
+	// the msgs of a just admitted client and the rejections of timed out ones, handled before any new transport event
+	queued_events: VecDeque<ServerTransportEvent>,
+	config: ServerConfig,
+	rates: HashMap<SocketAddr, InboundRate>,
+	// the server front end has to disconnect these with the reason
+	disconnects: Vec<(SocketAddr, DisconnectReason)>,
+}
+
+impl ClientRegistry {
+	pub(crate) fn new(config: ServerConfig) -> Self {
+		Self {
+			config,
+			..Self::default()
+		}
+	}
+
+	// ids are never reused, so a reconnecting client always gets a new one
+	pub(crate) fn register(&mut self, address: SocketAddr) -> ClientId {
+		let client_id = self.next_client_id;
+		self.next_client_id += 1;
+		self.addresses.insert(client_id, address);
+		// drop the stale id, in case the transport never reported the disconnect of the old client
+		if let Some(old_client_id) = self.ids.insert(address, client_id) {
+			self.addresses.remove(&old_client_id);
+			self.identities.remove(&old_client_id);
+		}
+		client_id
+	}
+
+	pub(crate) fn unregister(&mut self, address: SocketAddr) -> Option<ClientId> {
+		let client_id = self.ids.remove(&address)?;
+		self.addresses.remove(&client_id);
+		self.identities.remove(&client_id);
+		self.rates.remove(&address);
+		Some(client_id)
+	}
+
+	pub(crate) fn set_authenticator(&mut self, authenticator: impl Authenticator + 'static) {
+		self.authenticator = Some(Box::new(authenticator));
+	}
+
+	pub(crate) fn identity(&self, client_id: ClientId) -> Option<&str> {
+		self.identities.get(&client_id).map(String::as_str)
+	}
+
+	// has to be handled before the next transport event
+	pub(crate) fn pop_queued_event(&mut self) -> Option<ServerTransportEvent> {
+		self.expire_pending(Instant::now());
+		self.queued_events.pop_front()
+	}
+
+	// when the next pending client times out, the front ends have to wake up for it
+	pub(crate) fn next_pending_deadline(&self) -> Option<Instant> {
+		self.pending.values().filter_map(|pending_client| pending_client.deadline).min()
+	}
+
+	fn expire_pending(&mut self, now: Instant) {
+		let expired_addresses: Vec<SocketAddr> = self.pending.iter()
+			.filter(|(_address, pending_client)| pending_client.deadline.is_some_and(|deadline| deadline <= now))
+			.map(|(address, _pending_client)| *address)
+			.collect();
+		for address in expired_addresses {
+			let reason = ConnectionRejectedReason::Rejected("didn't send its connect payload in time".to_string());
+			self.reject::<()>(address, reason.clone());
+			self.queued_events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+		}
+	}
+
+	pub(crate) fn take_disconnects(&mut self) -> Vec<(SocketAddr, DisconnectReason)> {
+		std::mem::take(&mut self.disconnects)
+	}
+
+	fn reject<Msg>(&mut self, address: SocketAddr, reason: ConnectionRejectedReason) -> Option<ServerEvent<Msg>> {
+		self.pending.remove(&address);
+		self.rates.remove(&address);
+		self.disconnects.push((address, DisconnectReason::Rejected(reason.clone())));
+		Some(ServerEvent::ClientRejected(address, reason))
+	}
+
+	// clients that weren't admitted yet get rejected instead
+	fn kick<Msg>(&mut self, address: SocketAddr, reason: DisconnectReason) -> Option<ServerEvent<Msg>> {
+		if self.pending.contains_key(&address) {
+			return self.reject(address, ConnectionRejectedReason::Rejected(reason.to_string()));
+		}
+		let client_id = self.unregister(address)?;
+		self.disconnects.push((address, reason.clone()));
+		Some(ServerEvent::ClientDisconnected(client_id, reason))
+	}
+
+	// None if the msg is within the limits of the config
+	fn check_limits(&mut self, address: SocketAddr, size: usize, now: Instant) -> Option<DisconnectReason> {
+		if let Some(max_size) = self.config.max_msg_size.filter(|max_size| size > *max_size) {
+			return Some(DisconnectReason::MsgTooLarge {
+				size,
+				max_size,
+			});
+		}
+
+		let rate = self.rates.entry(address).or_insert(InboundRate {
+			window_start: now,
+			msgs: 0,
+			bytes: 0,
+		});
+		if now.duration_since(rate.window_start) >= RATE_WINDOW {
+			*rate = InboundRate {
+				window_start: now,
+				msgs: 0,
+				bytes: 0,
+			};
+		}
+		rate.msgs += 1;
+		rate.bytes += size;
+		let too_many_msgs = self.config.max_msgs_per_second.is_some_and(|max_msgs| rate.msgs > max_msgs);
+		let too_many_bytes = self.config.max_bytes_per_second.is_some_and(|max_bytes| rate.bytes > max_bytes);
+		(too_many_msgs || too_many_bytes).then_some(DisconnectReason::RateLimited)
+	}
+
+	fn handle_connect<Msg>(&mut self, address: SocketAddr, connect_payload: &[u8]) -> Option<ServerEvent<Msg>> {
+		// repeated or unexpected connect msgs of admitted clients are ignored
+		if !self.pending.contains_key(&address) {
+			return None;
+		}
+		let authenticator = self.authenticator.as_mut()?;
+		match authenticator.authenticate(address, connect_payload) {
+			Ok(identity) => {
+				let pending_msgs = self.pending.remove(&address).unwrap().msgs;
+				// the buffered msgs pass the limits again once they get handled
+				if let Some(rate) = self.rates.get_mut(&address) {
+					rate.msgs = rate.msgs.saturating_sub(pending_msgs.len() as u32);
+					rate.bytes = rate.bytes.saturating_sub(pending_msgs.iter().map(|msg| msg.data.len()).sum());
+				}
+				let client_id = self.register(address);
+				self.identities.insert(client_id, identity);
+				self.queued_events.extend(pending_msgs.into_iter().map(ServerTransportEvent::NewMsg));
+				Some(ServerEvent::NewClient(client_id))
+			},
+			Err(reason) => self.reject(address, ConnectionRejectedReason::Rejected(reason)),
+		}
+	}
+
+	pub(crate) fn id(&self, address: SocketAddr) -> Option<ClientId> {
+		self.ids.get(&address).copied()
+	}
+
+	pub(crate) fn address(&self, client_id: ClientId) -> Option<SocketAddr> {
+		self.addresses.get(&client_id).copied()
+	}
+
+	pub(crate) fn ids(&self) -> impl Iterator<Item = ClientId> + '_ {
+		self.addresses.keys().copied()
+	}
+
+	pub(crate) fn addresses_of(&self, client_ids: impl IntoIterator<Item = ClientId>) -> Vec<SocketAddr> {
+		client_ids.into_iter()
+			.filter_map(|client_id| self.address(client_id))
+			.collect()
+	}
+
+	pub(crate) fn addresses_except(&self, except_client_id: Option<ClientId>) -> Vec<SocketAddr> {
+		self.addresses.iter()
+			.filter(|(client_id, _address)| Some(**client_id) != except_client_id)
+			.map(|(_client_id, address)| *address)
+			.collect()
+	}
+
+	// returns None for events of clients that aren't known (anymore) or not admitted yet
+	pub(crate) fn handle_transport_event<Msg: DeserializeOwned>(&mut self, event: ServerTransportEvent, codec: &impl Codec) -> Option<ServerEvent<Msg>> {
+		match event {
+			ServerTransportEvent::NewClient(address) => {
+				if self.config.max_clients.is_some_and(|max_clients| self.ids.len() + self.pending.len() >= max_clients) {
+					return self.reject(address, ConnectionRejectedReason::ServerFull);
+				}
+				if self.authenticator.is_some() {
+					if self.config.max_pending_handshakes.is_some_and(|max_pending| self.pending.len() >= max_pending) {
+						return self.reject(address, ConnectionRejectedReason::TooManyPendingHandshakes);
+					}
+					self.pending.insert(address, PendingClient {
+						deadline: self.config.handshake_timeout.map(|handshake_timeout| Instant::now() + handshake_timeout),
+						msgs: Vec::new(),
+					});
+					return None;
+				}
+				let client_id = self.register(address);
+				Some(ServerEvent::NewClient(client_id))
+			},
+			ServerTransportEvent::ClientDisconnected(address, reason) => {
+				if self.pending.remove(&address).is_some() {
+					self.rates.remove(&address);
+					return None;
+				}
+				let client_id = self.unregister(address)?;
+				Some(ServerEvent::ClientDisconnected(client_id, reason))
+			},
+			ServerTransportEvent::NewMsg(transport_msg) => {
+				let address = transport_msg.sender_address;
+				if !self.pending.contains_key(&address) && !self.ids.contains_key(&address) {
+					return None;
+				}
+				if let Some(reason) = self.check_limits(address, transport_msg.data.len(), Instant::now()) {
+					return self.kick(address, reason);
+				}
+				if let Some((MsgKind::Connect, connect_payload)) = decode_msg(&transport_msg.data) {
+					return self.handle_connect(address, connect_payload);
+				}
+				if let Some(pending_client) = self.pending.get_mut(&address) {
+					if pending_client.msgs.len() < MAX_PENDING_MSGS {
+						pending_client.msgs.push(transport_msg);
+					}
+					else {
+						return self.reject(address, ConnectionRejectedReason::Rejected("sent too many msgs before connecting".to_string()));
+					}
+					return None;
+				}
+				let client_id = self.id(address)?;
+				let channel_id = transport_msg.channel_id;
+
+				let Some((kind, body)) = decode_msg(&transport_msg.data) else {
+					return Some(ServerEvent::FailedToParseMsg(client_id));
+				};
+				let Ok(msg) = codec.deserialize(body) else {
+					return Some(ServerEvent::FailedToParseMsg(client_id));
+				};
+
+				match kind {
+					MsgKind::Msg => {
+						Some(ServerEvent::NewMsg(ClientMsg {
+							client_id,
+							channel_id,
+							msg,
+						}))
+					},
+					MsgKind::Request(request_id) => {
+						Some(ServerEvent::Request {
+							client_id,
+							request_id: ClientRequestId {
+								client_id,
+								channel_id,
+								request_id,
+							},
+							msg,
+						})
+					},
+					// the server never sends requests, so there is nothing to respond to
+					MsgKind::Response(_) => Some(ServerEvent::FailedToParseMsg(client_id)),
+					MsgKind::Connect => unreachable!(),
+				}
+			},
+			ServerTransportEvent::FailedToReceiveMsg(error) => Some(ServerEvent::FailedToReceiveMsg(error)),
+			ServerTransportEvent::FailedToAcceptConnection(error) => Some(ServerEvent::FailedToAcceptConnection(error)),
+			ServerTransportEvent::ClientRejected(address, reason) => Some(ServerEvent::ClientRejected(address, reason)),
+		}
 	}
 }
@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::transport::{ChannelId, ClientTransportEvent, DisconnectReason, SendError, ServerConfig, ServerTransportEvent};
+
+pub mod tcp;
+pub mod udp;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// NOTE: The async transports have to be created inside of a tokio runtime, since they spawn their listen tasks on it
+pub trait AsyncServerTransport: Send {
+	// Ready(None) once the transport shut down
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ServerTransportEvent>>;
+	// fails with Disconnected if there is no client with this address
+	fn send<'a>(&'a mut self, address: SocketAddr, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>>;
+	// Addresses without a client are skipped, the first other error is returned after trying all addresses.
+	// Transports that can batch sends to multiple addresses should override this.
+	fn send_to_many<'a>(&'a mut self, addresses: &'a [SocketAddr], data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		Box::pin(async move {
+			let mut result = Ok(());
+			for address in addresses {
+				match self.send(*address, data, channel_id).await {
+					Ok(()) | Err(SendError::Disconnected) => {},
+					Err(e) => {
+						if result.is_ok() {
+							result = Err(e);
+						}
+					},
+				}
+			}
+			result
+		})
+	}
+	// sends the msgs that coalescing transports queued, transports that send right away have nothing to do
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		Box::pin(async { Ok(()) })
+	}
+	// The client gets notified with the reason, no ClientDisconnected event is emitted for it.
+	// It is gone right away, the future only delivers the notification and doesn't borrow the transport,
+	// so that the server can drive it while it keeps polling events.
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) -> BoxFuture<'static, ()>;
+	// transports that can turn connections away before they become clients should override this
+	fn apply_config(&mut self, _config: &ServerConfig) {}
+}
+
+pub trait AsyncClientTransport: Send {
+	// Ready(None) once the transport shut down
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ClientTransportEvent>>;
+	fn send<'a>(&'a mut self, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>>;
+	// sends the msgs that coalescing transports queued, transports that send right away have nothing to do
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		Box::pin(async { Ok(()) })
+	}
+	// whether msgs of the channel are guaranteed to arrive, streams like tcp deliver every channel reliably
+	fn is_reliable(&self, _channel_id: ChannelId) -> bool {
+		true
+	}
+}
@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use crate::transport::tcp::{FrameReader, TcpConfig, TcpMsg, frame_msg, frame_tcp_msg, rejection_frame};
+use crate::transport::{MAX_MSG_SIZE, ChannelId, ClientTransportEvent, Coalescing, ConnectionRejectedReason, DisconnectReason, SendError, ServerConfig, ServerTransportEvent, TransportMsg};
+use super::{AsyncClientTransport, AsyncServerTransport, BoxFuture};
+
+// the frames of a stream wait in its send queue until the next flush, if coalescing is enabled
+struct QueuedWriter {
+	writer: OwnedWriteHalf,
+	send_queue: Vec<u8>,
+}
+
+impl QueuedWriter {
+	fn new(writer: OwnedWriteHalf) -> Self {
+		Self {
+			writer,
+			send_queue: Vec::new(),
+		}
+	}
+
+	async fn send(&mut self, frame: &[u8], coalescing: Coalescing) -> io::Result<()> {
+		match coalescing {
+			Coalescing::Disabled => self.writer.write_all(frame).await,
+			Coalescing::Manual | Coalescing::Interval(_) => {
+				self.send_queue.extend_from_slice(frame);
+				Ok(())
+			},
+		}
+	}
+
+	async fn flush(&mut self) -> io::Result<()> {
+		if self.send_queue.is_empty() {
+			return Ok(());
+		}
+		let result = self.writer.write_all(&self.send_queue).await;
+		self.send_queue.clear();
+		result
+	}
+}
+
+enum WriterCommand {
+	Send(Vec<u8>),
+	Flush,
+	Disconnect(DisconnectReason),
+}
+
+// Every client has its own writer task, so that a slow client never holds up the sends to the others
+struct ClientHandle {
+	commands: UnboundedSender<WriterCommand>,
+	// dropping it stops the read task of the client
+	_stop_reading: oneshot::Sender<()>,
+}
+
+// NOTE: Behind a std mutex, which is never held across an await
+type ClientHandles = Arc<Mutex<HashMap<SocketAddr, ClientHandle>>>;
+
+pub struct AsyncTcpClientTransport {
+	writer: Arc<tokio::sync::Mutex<QueuedWriter>>,
+	event_receiver: UnboundedReceiver<ClientTransportEvent>,
+	listen_task: JoinHandle<()>,
+	server_disconnected: bool,
+	config: TcpConfig,
+}
+
+impl AsyncTcpClientTransport {
+	pub async fn new<A: ToSocketAddrs>(server_address: A) -> io::Result<Self> {
+		Self::with_config(server_address, TcpConfig::default()).await
+	}
+
+	pub async fn with_config<A: ToSocketAddrs>(server_address: A, config: TcpConfig) -> io::Result<Self> {
+		let stream = TcpStream::connect(server_address).await?;
+		let (reader, writer) = stream.into_split();
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let listen_task = tokio::spawn(Self::listen_task(reader, FrameReader::new(&config), sender));
+		let writer = Arc::new(tokio::sync::Mutex::new(QueuedWriter::new(writer)));
+		if let Coalescing::Interval(interval) = config.coalescing {
+			tokio::spawn(Self::flush_task(Arc::downgrade(&writer), interval));
+		}
+
+		Ok(Self {
+			writer,
+			event_receiver: receiver,
+			listen_task,
+			server_disconnected: false,
+			config,
+		})
+	}
+
+	// stops once the transport got dropped
+	// NOTE: Write errors are ignored, since the listen task notices the broken stream as well
+	async fn flush_task(writer: Weak<tokio::sync::Mutex<QueuedWriter>>, interval: Duration) {
+		let mut flush_interval = tokio::time::interval(interval);
+		loop {
+			flush_interval.tick().await;
+			let Some(writer) = writer.upgrade() else {
+				return;
+			};
+			let _ = writer.lock().await.flush().await;
+		}
+	}
+
+	fn track_write_error(&mut self, result: io::Result<()>) -> Result<(), SendError> {
+		result.map_err(|e| {
+			self.server_disconnected = true;
+			SendError::Io(e)
+		})
+	}
+
+	async fn listen_task(mut reader: OwnedReadHalf, mut frame_reader: FrameReader, sender: UnboundedSender<ClientTransportEvent>) {
+		let mut buffer = vec![0; MAX_MSG_SIZE];
+
+		loop {
+			match reader.read(&mut buffer).await {
+				Ok(0) => {
+					let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::Closed));
+					return;
+				},
+				Ok(bytes_read) => {
+					frame_reader.push(&buffer[..bytes_read]);
+					loop {
+						match frame_reader.next_frame() {
+							Ok(Some(frame)) => {
+								let event = match bincode::deserialize(&frame) {
+									Ok(TcpMsg::Msg(channel_id, data)) => ClientTransportEvent::NewMsg(channel_id, data),
+									Ok(TcpMsg::Disconnected(reason)) => {
+										let _ = sender.send(ClientTransportEvent::ServerDisconnected(reason));
+										return;
+									},
+									Err(e) => ClientTransportEvent::FailedToReceiveMsg(io::Error::other(e)),
+								};
+								if sender.send(event).is_err() {
+									return;
+								}
+							},
+							Ok(None) => break,
+							Err(e) => {
+								// the stream can't be resynchronized after a corrupted frame header
+								let _ = sender.send(ClientTransportEvent::FailedToReceiveMsg(e));
+								let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::ConnectionLost));
+								return;
+							},
+						}
+					}
+				},
+				Err(e) => {
+					match e.kind() {
+						io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+							let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::ConnectionLost));
+							return;
+						}
+						_ => {
+							if sender.send(ClientTransportEvent::FailedToReceiveMsg(e)).is_err() {
+								return;
+							}
+						},
+					}
+				}
+			}
+		}
+	}
+}
+
+impl Drop for AsyncTcpClientTransport {
+	fn drop(&mut self) {
+		self.listen_task.abort();
+	}
+}
+
+impl AsyncClientTransport for AsyncTcpClientTransport {
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ClientTransportEvent>> {
+		let event = self.event_receiver.poll_recv(cx);
+		if let Poll::Ready(Some(ClientTransportEvent::ServerDisconnected(_))) = &event {
+			self.server_disconnected = true;
+		}
+		event
+	}
+
+	// NOTE: A failed write doesn't emit an event itself, the listen task notices the broken stream as well
+	fn send<'a>(&'a mut self, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		Box::pin(async move {
+			if self.server_disconnected {
+				return Err(SendError::Disconnected);
+			}
+
+			let frame = frame_msg(data, channel_id, &self.config)?;
+			let result = self.writer.lock().await.send(&frame, self.config.coalescing).await;
+			self.track_write_error(result)
+		})
+	}
+
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		Box::pin(async move {
+			if self.server_disconnected {
+				return Err(SendError::Disconnected);
+			}
+
+			let result = self.writer.lock().await.flush().await;
+			self.track_write_error(result)
+		})
+	}
+}
+
+pub struct AsyncTcpServerTransport {
+	event_receiver: UnboundedReceiver<ServerTransportEvent>,
+	client_handles: ClientHandles,
+	// usize::MAX without a limit, connections over it are rejected before getting a task
+	max_clients: Arc<AtomicUsize>,
+	listen_task: JoinHandle<()>,
+	config: TcpConfig,
+	local_address: SocketAddr,
+}
+
+impl AsyncTcpServerTransport {
+	pub async fn bind_port(port: u16) -> io::Result<Self> {
+		Self::new(("0.0.0.0", port)).await
+	}
+
+	pub async fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, TcpConfig::default()).await
+	}
+
+	pub async fn with_config<A: ToSocketAddrs>(address: A, config: TcpConfig) -> io::Result<Self> {
+		let listener = TcpListener::bind(address).await?;
+		let local_address = listener.local_addr()?;
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let client_handles = ClientHandles::default();
+		let max_clients = Arc::new(AtomicUsize::new(usize::MAX));
+		let listen_task = tokio::spawn(Self::listen_task(listener, sender, client_handles.clone(), max_clients.clone(), config.clone()));
+
+		Ok(Self {
+			event_receiver: receiver,
+			client_handles,
+			max_clients,
+			listen_task,
+			config,
+			local_address,
+		})
+	}
+
+	// e.g. to find out the port the os picked for port 0
+	pub fn local_address(&self) -> SocketAddr {
+		self.local_address
+	}
+
+	// the client tasks are owned by the listen task, so aborting it stops all of them
+	async fn listen_task(listener: TcpListener, sender: UnboundedSender<ServerTransportEvent>, client_handles: ClientHandles, max_clients: Arc<AtomicUsize>, config: TcpConfig) {
+		let mut client_tasks = JoinSet::new();
+
+		loop {
+			tokio::select! {
+				accepted = listener.accept() => {
+					match accepted {
+						Ok((mut stream, address)) => {
+							if client_handles.lock().unwrap().len() >= max_clients.load(Ordering::Relaxed) {
+								let _ = stream.write_all(&rejection_frame(ConnectionRejectedReason::ServerFull)).await;
+								let _ = stream.shutdown().await;
+								if sender.send(ServerTransportEvent::ClientRejected(address, ConnectionRejectedReason::ServerFull)).is_err() {
+									return;
+								}
+								continue;
+							}
+
+							let (reader, writer) = stream.into_split();
+							let (command_sender, command_receiver) = mpsc::unbounded_channel();
+							let (stop_reading, stopped_reading) = oneshot::channel();
+							// inserted before the client task starts, which removes it again once the client is gone
+							client_handles.lock().unwrap().insert(address, ClientHandle {
+								commands: command_sender,
+								_stop_reading: stop_reading,
+							});
+							tokio::spawn(Self::writer_task(QueuedWriter::new(writer), command_receiver, config.coalescing));
+							client_tasks.spawn(Self::handle_client_task(reader, address, FrameReader::new(&config), sender.clone(), client_handles.clone(), stopped_reading));
+						},
+						Err(e) => {
+							if sender.send(ServerTransportEvent::FailedToAcceptConnection(e)).is_err() {
+								return;
+							}
+						},
+					}
+				},
+				Some(_) = client_tasks.join_next() => {},
+			}
+		}
+	}
+
+	// stops once its client got disconnected or the transport got dropped, since then nobody can send commands anymore
+	// NOTE: Write errors end it as well, the read task notices the broken stream and reports the disconnect
+	async fn writer_task(mut writer: QueuedWriter, mut commands: UnboundedReceiver<WriterCommand>, coalescing: Coalescing) {
+		let mut flush_interval = match coalescing {
+			Coalescing::Interval(interval) => Some(tokio::time::interval(interval)),
+			Coalescing::Disabled | Coalescing::Manual => None,
+		};
+
+		loop {
+			let flush_due = async {
+				match &mut flush_interval {
+					Some(flush_interval) => flush_interval.tick().await,
+					None => std::future::pending().await,
+				}
+			};
+			let command = tokio::select! {
+				command = commands.recv() => command,
+				_ = flush_due => Some(WriterCommand::Flush),
+			};
+
+			let result = match command {
+				Some(WriterCommand::Send(frame)) => writer.send(&frame, coalescing).await,
+				Some(WriterCommand::Flush) => writer.flush().await,
+				Some(WriterCommand::Disconnect(reason)) => {
+					// queued msgs still go out before the kick
+					let _ = writer.flush().await;
+					let _ = writer.writer.write_all(&frame_tcp_msg(&TcpMsg::Disconnected(reason))).await;
+					let _ = writer.writer.shutdown().await;
+					return;
+				},
+				None => {
+					let _ = writer.flush().await;
+					return;
+				},
+			};
+			if result.is_err() {
+				return;
+			}
+		}
+	}
+
+	async fn handle_client_task(reader: OwnedReadHalf, address: SocketAddr, frame_reader: FrameReader, sender: UnboundedSender<ServerTransportEvent>, client_handles: ClientHandles, stopped_reading: oneshot::Receiver<()>) {
+		if sender.send(ServerTransportEvent::NewClient(address)).is_err() {
+			return;
+		}
+
+		let reason = tokio::select! {
+			reason = Self::receive_client_msgs(reader, address, frame_reader, &sender) => reason,
+			_ = stopped_reading => return,
+		};
+		// kicked clients were already removed and shouldn't be reported as disconnected
+		let was_connected = client_handles.lock().unwrap().remove(&address).is_some();
+		if let Some(reason) = reason {
+			if was_connected {
+				let _ = sender.send(ServerTransportEvent::ClientDisconnected(address, reason));
+			}
+		}
+	}
+
+	fn send_command(&self, address: SocketAddr, command: WriterCommand) -> Result<(), SendError> {
+		let client_handles = self.client_handles.lock().unwrap();
+		let handle = client_handles.get(&address).ok_or(SendError::Disconnected)?;
+		handle.commands.send(command).map_err(|_| SendError::Disconnected)
+	}
+
+	// returns the reason once the client disconnected, or None if the transport got dropped
+	async fn receive_client_msgs(mut reader: OwnedReadHalf, address: SocketAddr, mut frame_reader: FrameReader, sender: &UnboundedSender<ServerTransportEvent>) -> Option<DisconnectReason> {
+		let mut buffer = vec![0; MAX_MSG_SIZE];
+
+		loop {
+			match reader.read(&mut buffer).await {
+				Ok(0) => return Some(DisconnectReason::Closed),
+				Ok(bytes_read) => {
+					frame_reader.push(&buffer[..bytes_read]);
+					loop {
+						match frame_reader.next_frame() {
+							Ok(Some(frame)) => {
+								let event = match bincode::deserialize(&frame) {
+									Ok(TcpMsg::Msg(channel_id, data)) => {
+										ServerTransportEvent::NewMsg(
+											TransportMsg {
+												sender_address: address,
+												channel_id,
+												data,
+											}
+										)
+									},
+									Ok(TcpMsg::Disconnected(reason)) => return Some(reason),
+									Err(e) => ServerTransportEvent::FailedToReceiveMsg(io::Error::other(e)),
+								};
+								if sender.send(event).is_err() {
+									return None;
+								}
+							},
+							Ok(None) => break,
+							Err(e) => {
+								// the stream can't be resynchronized after a corrupted frame header
+								let _ = sender.send(ServerTransportEvent::FailedToReceiveMsg(e));
+								return Some(DisconnectReason::ConnectionLost);
+							},
+						}
+					}
+				},
+				Err(e) => {
+					match e.kind() {
+						io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+							return Some(DisconnectReason::ConnectionLost);
+						}
+						_ => {
+							if sender.send(ServerTransportEvent::FailedToReceiveMsg(e)).is_err() {
+								return None;
+							}
+						},
+					}
+				}
+			}
+		}
+	}
+}
+
+impl Drop for AsyncTcpServerTransport {
+	fn drop(&mut self) {
+		self.listen_task.abort();
+	}
+}
+
+impl AsyncServerTransport for AsyncTcpServerTransport {
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ServerTransportEvent>> {
+		self.event_receiver.poll_recv(cx)
+	}
+
+	// NOTE: The frames only get queued for the writer task of the client, whose read task reports broken streams
+	fn send<'a>(&'a mut self, address: SocketAddr, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		Box::pin(async move {
+			let frame = frame_msg(data, channel_id, &self.config)?;
+			self.send_command(address, WriterCommand::Send(frame))
+		})
+	}
+
+	fn send_to_many<'a>(&'a mut self, addresses: &'a [SocketAddr], data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		Box::pin(async move {
+			let frame = frame_msg(data, channel_id, &self.config)?;
+			let client_handles = self.client_handles.lock().unwrap();
+			for handle in addresses.iter().filter_map(|address| client_handles.get(address)) {
+				let _ = handle.commands.send(WriterCommand::Send(frame.clone()));
+			}
+			Ok(())
+		})
+	}
+
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		Box::pin(async move {
+			for handle in self.client_handles.lock().unwrap().values() {
+				let _ = handle.commands.send(WriterCommand::Flush);
+			}
+			Ok(())
+		})
+	}
+
+	// the read task stops right away, the writer task once it sent the queued msgs and the disconnect msg
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) -> BoxFuture<'static, ()> {
+		let handle = self.client_handles.lock().unwrap().remove(&address);
+		if let Some(handle) = handle {
+			let _ = handle.commands.send(WriterCommand::Disconnect(reason));
+		}
+		Box::pin(std::future::ready(()))
+	}
+
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.max_clients.store(config.max_clients.unwrap_or(usize::MAX), Ordering::Relaxed);
+	}
+}
@@ -0,0 +1,298 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use crate::transport::udp::{ClientEndpoint, ServerEndpoint, UdpConfig, UPDATE_INTERVAL};
+use crate::transport::{MAX_MSG_SIZE, ChannelId, ClientTransportEvent, DisconnectReason, SendError, ServerConfig, ServerTransportEvent};
+use super::{AsyncClientTransport, AsyncServerTransport, BoxFuture};
+
+// NOTE: The endpoints are behind a std mutex, which is never held across an await
+pub struct AsyncUdpClientTransport {
+	socket: Arc<UdpSocket>,
+	endpoint: Arc<Mutex<ClientEndpoint>>,
+	event_receiver: UnboundedReceiver<ClientTransportEvent>,
+	listen_task: JoinHandle<()>,
+}
+
+impl AsyncUdpClientTransport {
+	pub async fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, UdpConfig::default()).await
+	}
+
+	pub async fn with_config<A: ToSocketAddrs>(address: A, config: UdpConfig) -> io::Result<Self> {
+		let socket = UdpSocket::bind("0.0.0.0:0").await?;
+		// a connected socket only receives datagrams of the server
+		socket.connect(address).await?;
+		let socket = Arc::new(socket);
+		let endpoint = Arc::new(Mutex::new(ClientEndpoint::new(config, Instant::now())));
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let listen_task = tokio::spawn(Self::listen_task(socket.clone(), endpoint.clone(), sender));
+
+		Ok(Self {
+			socket,
+			endpoint,
+			event_receiver: receiver,
+			listen_task,
+		})
+	}
+
+	// the mtu negotiated with the server, None until the connection got accepted
+	pub fn mtu(&self) -> Option<usize> {
+		self.endpoint.lock().unwrap().mtu()
+	}
+
+	async fn listen_task(socket: Arc<UdpSocket>, endpoint: Arc<Mutex<ClientEndpoint>>, sender: UnboundedSender<ClientTransportEvent>) {
+		let mut buffer = vec![0; MAX_MSG_SIZE];
+		let mut update_interval = tokio::time::interval(UPDATE_INTERVAL);
+
+		loop {
+			let received = tokio::select! {
+				received = socket.recv(&mut buffer) => Some(received),
+				_ = update_interval.tick() => None,
+			};
+
+			let (outgoing, disconnected) = {
+				let mut endpoint = endpoint.lock().unwrap();
+				let now = Instant::now();
+
+				match received {
+					Some(Ok(bytes_read)) => endpoint.receive(&buffer[..bytes_read], now),
+					Some(Err(e)) => {
+						match e.kind() {
+							io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+								endpoint.server_disconnected(DisconnectReason::ConnectionLost);
+							},
+							_ => {
+								if sender.send(ClientTransportEvent::FailedToReceiveMsg(e)).is_err() {
+									return;
+								}
+							},
+						}
+					},
+					None => endpoint.update(now),
+				}
+
+				while let Some(event) = endpoint.poll_event() {
+					if sender.send(event).is_err() {
+						return;
+					}
+				}
+				(endpoint.drain_outgoing().collect::<Vec<_>>(), endpoint.is_disconnected())
+			};
+
+			for data in outgoing {
+				let _ = socket.send(&data).await;
+			}
+			if disconnected {
+				return;
+			}
+		}
+	}
+}
+
+impl Drop for AsyncUdpClientTransport {
+	fn drop(&mut self) {
+		self.listen_task.abort();
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.disconnect();
+		for data in endpoint.drain_outgoing() {
+			let _ = self.socket.try_send(&data);
+		}
+	}
+}
+
+impl AsyncClientTransport for AsyncUdpClientTransport {
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ClientTransportEvent>> {
+		self.event_receiver.poll_recv(cx)
+	}
+
+	fn send<'a>(&'a mut self, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		Box::pin(async move {
+			let outgoing: Vec<_> = {
+				let mut endpoint = self.endpoint.lock().unwrap();
+				endpoint.send(data, channel_id, Instant::now())?;
+				endpoint.drain_outgoing().collect()
+			};
+			for data in outgoing {
+				self.socket.send(&data).await?;
+			}
+			Ok(())
+		})
+	}
+
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		Box::pin(async move {
+			let outgoing: Vec<_> = {
+				let mut endpoint = self.endpoint.lock().unwrap();
+				endpoint.flush(Instant::now());
+				endpoint.drain_outgoing().collect()
+			};
+			for data in outgoing {
+				self.socket.send(&data).await?;
+			}
+			Ok(())
+		})
+	}
+
+	fn is_reliable(&self, channel_id: ChannelId) -> bool {
+		self.endpoint.lock().unwrap().is_reliable(channel_id)
+	}
+}
+
+pub struct AsyncUdpServerTransport {
+	event_receiver: UnboundedReceiver<ServerTransportEvent>,
+	socket: Arc<UdpSocket>,
+	endpoint: Arc<Mutex<ServerEndpoint>>,
+	listen_task: JoinHandle<()>,
+}
+
+impl AsyncUdpServerTransport {
+	pub async fn bind_port(port: u16) -> io::Result<Self> {
+		Self::new(("0.0.0.0", port)).await
+	}
+
+	pub async fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, UdpConfig::default()).await
+	}
+
+	pub async fn with_config<A: ToSocketAddrs>(address: A, config: UdpConfig) -> io::Result<Self> {
+		let socket = Arc::new(UdpSocket::bind(address).await?);
+		let endpoint = Arc::new(Mutex::new(ServerEndpoint::new(config)));
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let listen_task = tokio::spawn(Self::listen_task(socket.clone(), endpoint.clone(), sender));
+
+		Ok(Self {
+			event_receiver: receiver,
+			socket,
+			endpoint,
+			listen_task,
+		})
+	}
+
+	// the mtu negotiated with the client at this address
+	pub fn mtu(&self, address: SocketAddr) -> Option<usize> {
+		self.endpoint.lock().unwrap().mtu(address)
+	}
+
+	async fn listen_task(socket: Arc<UdpSocket>, endpoint: Arc<Mutex<ServerEndpoint>>, sender: UnboundedSender<ServerTransportEvent>) {
+		let mut buffer = vec![0; MAX_MSG_SIZE];
+		let mut update_interval = tokio::time::interval(UPDATE_INTERVAL);
+
+		loop {
+			let received = tokio::select! {
+				received = socket.recv_from(&mut buffer) => Some(received),
+				_ = update_interval.tick() => None,
+			};
+
+			let outgoing: Vec<_> = {
+				let mut endpoint = endpoint.lock().unwrap();
+				let now = Instant::now();
+
+				match received {
+					Some(Ok((bytes_read, address))) => endpoint.receive(address, &buffer[..bytes_read], now),
+					Some(Err(e)) => {
+						if sender.send(ServerTransportEvent::FailedToReceiveMsg(e)).is_err() {
+							return;
+						}
+					},
+					None => endpoint.update(now),
+				}
+
+				while let Some(event) = endpoint.poll_event() {
+					if sender.send(event).is_err() {
+						return;
+					}
+				}
+				endpoint.drain_outgoing().collect()
+			};
+
+			for (address, data) in outgoing {
+				let _ = socket.send_to(&data, address).await;
+			}
+		}
+	}
+
+	// returns the first error after trying to send everything
+	async fn send_outgoing(&self, outgoing: Vec<(SocketAddr, Vec<u8>)>) -> Result<(), SendError> {
+		let mut result = Ok(());
+		for (address, data) in outgoing {
+			if let Err(e) = self.socket.send_to(&data, address).await {
+				if result.is_ok() {
+					result = Err(SendError::Io(e));
+				}
+			}
+		}
+		result
+	}
+}
+
+impl Drop for AsyncUdpServerTransport {
+	fn drop(&mut self) {
+		self.listen_task.abort();
+	}
+}
+
+impl AsyncServerTransport for AsyncUdpServerTransport {
+	fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<ServerTransportEvent>> {
+		self.event_receiver.poll_recv(cx)
+	}
+
+	fn send<'a>(&'a mut self, address: SocketAddr, data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		let outgoing = {
+			let mut endpoint = self.endpoint.lock().unwrap();
+			if let Err(e) = endpoint.send(address, data, channel_id, Instant::now()) {
+				return Box::pin(std::future::ready(Err(e)));
+			}
+			endpoint.drain_outgoing().collect()
+		};
+		Box::pin(self.send_outgoing(outgoing))
+	}
+
+	fn send_to_many<'a>(&'a mut self, addresses: &'a [SocketAddr], data: &'a [u8], channel_id: ChannelId) -> BoxFuture<'a, Result<(), SendError>> {
+		let outgoing = {
+			let mut endpoint = self.endpoint.lock().unwrap();
+			if let Err(e) = endpoint.check_msg(data, channel_id) {
+				return Box::pin(std::future::ready(Err(e)));
+			}
+			let now = Instant::now();
+			for address in addresses {
+				// clients that disconnected in the meantime are skipped
+				let _ = endpoint.send(*address, data, channel_id, now);
+			}
+			endpoint.drain_outgoing().collect()
+		};
+		Box::pin(self.send_outgoing(outgoing))
+	}
+
+	fn flush(&mut self) -> BoxFuture<'_, Result<(), SendError>> {
+		let outgoing = {
+			let mut endpoint = self.endpoint.lock().unwrap();
+			endpoint.flush(Instant::now());
+			endpoint.drain_outgoing().collect()
+		};
+		Box::pin(self.send_outgoing(outgoing))
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) -> BoxFuture<'static, ()> {
+		let outgoing: Vec<_> = {
+			let mut endpoint = self.endpoint.lock().unwrap();
+			endpoint.disconnect(address, reason);
+			endpoint.drain_outgoing().collect()
+		};
+		let socket = self.socket.clone();
+		Box::pin(async move {
+			for (address, data) in outgoing {
+				let _ = socket.send_to(&data, address).await;
+			}
+		})
+	}
+
+	// connection requests over the limits get rejected during the handshake
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.endpoint.lock().unwrap().apply_config(config);
+	}
+}
@@ -0,0 +1,580 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use crate::transport::{DEFAULT_HANDSHAKE_TIMEOUT, ChannelId, ClientTransport, ClientTransportEvent, ConnectionRejectedReason, DisconnectReason, SendError, ServerConfig, ServerTransport, ServerTransportEvent, TransportMsg};
+
+// The client knows the static public key of the server in advance, both sides add an ephemeral key.
+// Only the real server can derive the session keys, which it proves in its welcome.
+// NOTE: Only the msgs are sealed, not the datagrams of the wrapped transport. The udp handshake, packet headers
+// like sequence numbers and acks, heartbeats and disconnects stay in plain text and can be read or forged.
+// An attacker on the path can therefore still drop the connection or make the transport resend and reorder msgs,
+// but can't read, change or inject any msg.
+
+const HELLO: u8 = 0;
+const WELCOME: u8 = 1;
+const DATA: u8 = 2;
+const KEY_SIZE: usize = 32;
+const TAG_SIZE: usize = 16;
+const COUNTER_SIZE: usize = 8;
+const HELLO_SIZE: usize = 1 + KEY_SIZE;
+const WELCOME_SIZE: usize = 1 + KEY_SIZE + TAG_SIZE;
+const DATA_HEADER_SIZE: usize = 1 + COUNTER_SIZE;
+// data nonces have zeros in these bytes, so the welcome can't collide with them
+const WELCOME_NONCE: [u8; 12] = [0xff; 12];
+const KEY_DERIVATION_SALT: &[u8] = b"client_server encryption v1";
+
+// NOTE: Handshake msgs go over this channel and are resent until answered, so it doesn't have to be reliable
+const HANDSHAKE_CHANNEL: ChannelId = 0;
+const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_millis(250);
+// server msgs on other channels may overtake the welcome
+const MAX_EARLY_MSGS: usize = 256;
+// how far msgs of a channel may arrive out of order before they count as replayed
+const REPLAY_WINDOW_SIZE: u64 = 1024;
+
+pub struct ServerKey {
+	secret: StaticSecret,
+}
+
+impl ServerKey {
+	pub fn generate() -> Self {
+		Self {
+			secret: StaticSecret::random_from_rng(OsRng),
+		}
+	}
+
+	pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Self {
+		Self {
+			secret: StaticSecret::from(bytes),
+		}
+	}
+
+	pub fn to_bytes(&self) -> [u8; KEY_SIZE] {
+		self.secret.to_bytes()
+	}
+
+	// clients need this to connect
+	pub fn public_key(&self) -> [u8; KEY_SIZE] {
+		PublicKey::from(&self.secret).to_bytes()
+	}
+}
+
+struct ReplayWindow {
+	// the highest counter received so far, counters start at 1
+	highest: u64,
+	received: [u64; (REPLAY_WINDOW_SIZE / 64) as usize],
+}
+
+impl Default for ReplayWindow {
+	fn default() -> Self {
+		Self {
+			highest: 0,
+			received: [0; (REPLAY_WINDOW_SIZE / 64) as usize],
+		}
+	}
+}
+
+impl ReplayWindow {
+	fn bit(counter: u64) -> (usize, u64) {
+		let index = counter % REPLAY_WINDOW_SIZE;
+		((index / 64) as usize, 1 << (index % 64))
+	}
+
+	fn is_new(&self, counter: u64) -> bool {
+		if counter > self.highest {
+			return true;
+		}
+		if counter == 0 || self.highest - counter >= REPLAY_WINDOW_SIZE {
+			return false;
+		}
+		let (word, bit) = Self::bit(counter);
+		self.received[word] & bit == 0
+	}
+
+	// only for counters that passed is_new and belong to an authentic msg
+	fn mark(&mut self, counter: u64) {
+		if counter > self.highest {
+			if counter - self.highest >= REPLAY_WINDOW_SIZE {
+				self.received = [0; (REPLAY_WINDOW_SIZE / 64) as usize];
+			}
+			else {
+				for skipped in self.highest + 1..counter {
+					let (word, bit) = Self::bit(skipped);
+					self.received[word] &= !bit;
+				}
+			}
+			self.highest = counter;
+		}
+		let (word, bit) = Self::bit(counter);
+		self.received[word] |= bit;
+	}
+}
+
+struct SessionKeys {
+	send_cipher: ChaCha20Poly1305,
+	receive_cipher: ChaCha20Poly1305,
+	send_counters: HashMap<ChannelId, u64>,
+	replay_windows: HashMap<ChannelId, ReplayWindow>,
+}
+
+impl SessionKeys {
+	// both sides end up with the same keys, each one sends with the key the other one receives with
+	fn derive(shared_secrets: [[u8; KEY_SIZE]; 2], transcript: &[u8], is_client: bool) -> Self {
+		let hkdf = Hkdf::<Sha256>::new(Some(KEY_DERIVATION_SALT), shared_secrets.as_flattened());
+		let mut keys = [0; 2 * KEY_SIZE];
+		hkdf.expand(transcript, &mut keys).unwrap();
+		let (client_key, server_key) = keys.split_at(KEY_SIZE);
+		let (send_key, receive_key) = if is_client { (client_key, server_key) } else { (server_key, client_key) };
+		Self {
+			send_cipher: ChaCha20Poly1305::new(Key::from_slice(send_key)),
+			receive_cipher: ChaCha20Poly1305::new(Key::from_slice(receive_key)),
+			send_counters: HashMap::new(),
+			replay_windows: HashMap::new(),
+		}
+	}
+
+	fn nonce(channel_id: ChannelId, counter: u64) -> Nonce {
+		let mut nonce = [0; 12];
+		nonce[0] = channel_id;
+		nonce[4..].copy_from_slice(&counter.to_le_bytes());
+		nonce.into()
+	}
+
+	fn encrypt(&mut self, data: &[u8], channel_id: ChannelId) -> Vec<u8> {
+		let counter = self.send_counters.entry(channel_id).or_insert(0);
+		*counter += 1;
+		let counter = *counter;
+		let ciphertext = self.send_cipher.encrypt(&Self::nonce(channel_id, counter), Payload {
+			msg: data,
+			aad: &[DATA, channel_id],
+		}).unwrap();
+
+		let mut packet = Vec::with_capacity(DATA_HEADER_SIZE + ciphertext.len());
+		packet.push(DATA);
+		packet.extend_from_slice(&counter.to_le_bytes());
+		packet.extend_from_slice(&ciphertext);
+		packet
+	}
+
+	// None for forged, corrupted and replayed msgs
+	fn decrypt(&mut self, packet: &[u8], channel_id: ChannelId) -> Option<Vec<u8>> {
+		if packet.len() < DATA_HEADER_SIZE + TAG_SIZE || packet[0] != DATA {
+			return None;
+		}
+		let counter = u64::from_le_bytes(packet[1..DATA_HEADER_SIZE].try_into().unwrap());
+		let replay_window = self.replay_windows.entry(channel_id).or_default();
+		if !replay_window.is_new(counter) {
+			return None;
+		}
+		let data = self.receive_cipher.decrypt(&Self::nonce(channel_id, counter), Payload {
+			msg: &packet[DATA_HEADER_SIZE..],
+			aad: &[DATA, channel_id],
+		}).ok()?;
+		replay_window.mark(counter);
+		Some(data)
+	}
+
+	fn welcome_tag(&self, transcript: &[u8]) -> Vec<u8> {
+		// the server sends with the key the client receives with
+		self.send_cipher.encrypt(&WELCOME_NONCE.into(), Payload {
+			msg: &[],
+			aad: transcript,
+		}).unwrap()
+	}
+
+	fn verify_welcome_tag(&self, transcript: &[u8], tag: &[u8]) -> bool {
+		self.receive_cipher.decrypt(&WELCOME_NONCE.into(), Payload {
+			msg: tag,
+			aad: transcript,
+		}).is_ok()
+	}
+}
+
+fn transcript(client_ephemeral: &[u8; KEY_SIZE], server_ephemeral: &[u8; KEY_SIZE], server_static: &[u8; KEY_SIZE]) -> Vec<u8> {
+	[client_ephemeral.as_slice(), server_ephemeral, server_static].concat()
+}
+
+struct ServerSession {
+	client_ephemeral: [u8; KEY_SIZE],
+	// resent if the client repeats its hello because the welcome got lost
+	welcome: Vec<u8>,
+	keys: SessionKeys,
+}
+
+// Wraps a transport like udp into an authenticated key exchange and encrypts every msg with its own nonce.
+// Forged, corrupted and replayed msgs are dropped silently, the transport underneath stays unprotected.
+pub struct EncryptedServerTransport<T: ServerTransport> {
+	transport: T,
+	key: ServerKey,
+	sessions: HashMap<SocketAddr, ServerSession>,
+	// connected clients of the wrapped transport that didn't send their hello yet, since when
+	pending_handshakes: HashMap<SocketAddr, Instant>,
+	events: VecDeque<ServerTransportEvent>,
+	max_pending_handshakes: Option<usize>,
+	handshake_timeout: Option<Duration>,
+}
+
+impl<T: ServerTransport> EncryptedServerTransport<T> {
+	pub fn new(transport: T, key: ServerKey) -> Self {
+		Self {
+			transport,
+			key,
+			sessions: HashMap::new(),
+			pending_handshakes: HashMap::new(),
+			events: VecDeque::new(),
+			max_pending_handshakes: None,
+			handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+		}
+	}
+
+	fn expire_handshakes(&mut self) {
+		let Some(handshake_timeout) = self.handshake_timeout else {
+			return;
+		};
+		let now = Instant::now();
+		let expired_addresses: Vec<SocketAddr> = self.pending_handshakes.iter()
+			.filter(|(_address, started)| now.duration_since(**started) >= handshake_timeout)
+			.map(|(address, _started)| *address)
+			.collect();
+		for address in expired_addresses {
+			self.pending_handshakes.remove(&address);
+			let reason = ConnectionRejectedReason::HandshakeFailed("the handshake timed out".to_string());
+			self.transport.disconnect(address, DisconnectReason::Rejected(reason.clone()));
+			self.events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+		}
+	}
+
+	fn next_handshake_expiry(&self) -> Option<Instant> {
+		let handshake_timeout = self.handshake_timeout?;
+		self.pending_handshakes.values()
+			.map(|started| *started + handshake_timeout)
+			.min()
+	}
+
+	fn handle_hello(&mut self, address: SocketAddr, hello: &[u8]) {
+		if hello.len() != HELLO_SIZE {
+			return;
+		}
+		let client_ephemeral: [u8; KEY_SIZE] = hello[1..].try_into().unwrap();
+		if let Some(session) = self.sessions.get(&address) {
+			if session.client_ephemeral == client_ephemeral {
+				let _ = self.transport.send(address, &session.welcome, HANDSHAKE_CHANNEL);
+				return;
+			}
+			// the client restarted with the same address, before the wrapped transport noticed
+			self.sessions.remove(&address);
+			self.events.push_back(ServerTransportEvent::ClientDisconnected(address, DisconnectReason::ConnectionLost));
+		}
+		self.pending_handshakes.remove(&address);
+
+		let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+		let server_ephemeral = PublicKey::from(&ephemeral_secret).to_bytes();
+		let client_public_key = PublicKey::from(client_ephemeral);
+		let shared_secrets = [
+			ephemeral_secret.diffie_hellman(&client_public_key).to_bytes(),
+			self.key.secret.diffie_hellman(&client_public_key).to_bytes(),
+		];
+		let transcript = transcript(&client_ephemeral, &server_ephemeral, &self.key.public_key());
+		let keys = SessionKeys::derive(shared_secrets, &transcript, false);
+		let welcome = [&[WELCOME], server_ephemeral.as_slice(), &keys.welcome_tag(&transcript)].concat();
+
+		let _ = self.transport.send(address, &welcome, HANDSHAKE_CHANNEL);
+		self.sessions.insert(address, ServerSession {
+			client_ephemeral,
+			welcome,
+			keys,
+		});
+		self.events.push_back(ServerTransportEvent::NewClient(address));
+	}
+
+	fn handle_transport_event(&mut self, event: ServerTransportEvent) {
+		match event {
+			// the client only counts as connected once it started the handshake
+			ServerTransportEvent::NewClient(address) => {
+				if self.max_pending_handshakes.is_some_and(|max_pending| self.pending_handshakes.len() >= max_pending) {
+					let reason = ConnectionRejectedReason::TooManyPendingHandshakes;
+					self.transport.disconnect(address, DisconnectReason::Rejected(reason.clone()));
+					self.events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+					return;
+				}
+				self.pending_handshakes.insert(address, Instant::now());
+			},
+			ServerTransportEvent::ClientDisconnected(address, reason) => {
+				self.pending_handshakes.remove(&address);
+				if self.sessions.remove(&address).is_some() {
+					self.events.push_back(ServerTransportEvent::ClientDisconnected(address, reason));
+				}
+			},
+			ServerTransportEvent::NewMsg(msg) => {
+				match msg.data.first() {
+					Some(&HELLO) => self.handle_hello(msg.sender_address, &msg.data),
+					Some(&DATA) => {
+						let Some(session) = self.sessions.get_mut(&msg.sender_address) else {
+							return;
+						};
+						if let Some(data) = session.keys.decrypt(&msg.data, msg.channel_id) {
+							self.events.push_back(ServerTransportEvent::NewMsg(TransportMsg {
+								sender_address: msg.sender_address,
+								channel_id: msg.channel_id,
+								data,
+							}));
+						}
+					},
+					_ => {},
+				}
+			},
+			event => self.events.push_back(event),
+		}
+	}
+}
+
+impl<T: ServerTransport> ServerTransport for EncryptedServerTransport<T> {
+	fn receive_event(&mut self) -> Option<ServerTransportEvent> {
+		self.expire_handshakes();
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			let event = self.transport.receive_event()?;
+			self.handle_transport_event(event);
+		}
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			self.expire_handshakes();
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			// also wakes up for the next handshake that times out
+			let wake_up = deadline.into_iter().chain(self.next_handshake_expiry()).min();
+			let now = Instant::now();
+			match self.transport.wait_event(wake_up.map(|wake_up| wake_up.saturating_duration_since(now))) {
+				Some(event) => self.handle_transport_event(event),
+				None => {
+					let now = Instant::now();
+					// returning early means the transport shut down
+					if deadline.is_some_and(|deadline| deadline <= now) || wake_up.is_none_or(|wake_up| now < wake_up) {
+						return None;
+					}
+				},
+			}
+		}
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let session = self.sessions.get_mut(&address).ok_or(SendError::Disconnected)?;
+		let packet = session.keys.encrypt(data, channel_id);
+		self.transport.send(address, &packet, channel_id)
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		self.sessions.remove(&address);
+		self.pending_handshakes.remove(&address);
+		self.transport.disconnect(address, reason);
+	}
+
+	// clients count as pending until they sent their hello
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.max_pending_handshakes = config.max_pending_handshakes;
+		self.handshake_timeout = config.handshake_timeout;
+		self.transport.apply_config(config);
+	}
+}
+
+enum ClientHandshake {
+	Pending {
+		ephemeral_secret: StaticSecret,
+		started: Instant,
+		last_hello: Instant,
+		// msgs sent before the handshake is done
+		queued_msgs: Vec<(ChannelId, Vec<u8>)>,
+		// msgs of the server that overtook its welcome
+		early_msgs: Vec<(ChannelId, Vec<u8>)>,
+	},
+	Done(SessionKeys),
+	Failed,
+}
+
+pub struct EncryptedClientTransport<T: ClientTransport> {
+	transport: T,
+	server_public_key: [u8; KEY_SIZE],
+	handshake: ClientHandshake,
+	events: VecDeque<ClientTransportEvent>,
+}
+
+impl<T: ClientTransport> EncryptedClientTransport<T> {
+	// connecting fails with a rejection if no server with this key answers
+	pub fn new(transport: T, server_public_key: [u8; KEY_SIZE]) -> Self {
+		let now = Instant::now();
+		let mut client = Self {
+			transport,
+			server_public_key,
+			handshake: ClientHandshake::Pending {
+				ephemeral_secret: StaticSecret::random_from_rng(OsRng),
+				started: now,
+				last_hello: now,
+				queued_msgs: Vec::new(),
+				early_msgs: Vec::new(),
+			},
+			events: VecDeque::new(),
+		};
+		client.send_hello();
+		client
+	}
+
+	fn send_hello(&mut self) {
+		if let ClientHandshake::Pending { ephemeral_secret, .. } = &self.handshake {
+			let hello = [&[HELLO], PublicKey::from(ephemeral_secret).as_bytes().as_slice()].concat();
+			// NOTE: A failed hello is just resent, a broken transport reports that on its own
+			let _ = self.transport.send(&hello, HANDSHAKE_CHANNEL);
+		}
+	}
+
+	// resends the hello and gives up on the handshake eventually
+	fn update_handshake(&mut self) {
+		let ClientHandshake::Pending { started, last_hello, .. } = &mut self.handshake else {
+			return;
+		};
+		let now = Instant::now();
+		if now - *started >= DEFAULT_HANDSHAKE_TIMEOUT {
+			self.handshake = ClientHandshake::Failed;
+			self.events.push_back(ClientTransportEvent::ConnectionRejected(ConnectionRejectedReason::HandshakeFailed("the server didn't answer with the expected key".to_string())));
+		}
+		else if now - *last_hello >= HANDSHAKE_RESEND_INTERVAL {
+			*last_hello = now;
+			self.send_hello();
+		}
+	}
+
+	fn handle_welcome(&mut self, welcome: &[u8]) {
+		let ClientHandshake::Pending { ephemeral_secret, .. } = &self.handshake else {
+			return;
+		};
+		if welcome.len() != WELCOME_SIZE {
+			return;
+		}
+		let server_ephemeral: [u8; KEY_SIZE] = welcome[1..1 + KEY_SIZE].try_into().unwrap();
+		let shared_secrets = [
+			ephemeral_secret.diffie_hellman(&PublicKey::from(server_ephemeral)).to_bytes(),
+			ephemeral_secret.diffie_hellman(&PublicKey::from(self.server_public_key)).to_bytes(),
+		];
+		let client_ephemeral = PublicKey::from(ephemeral_secret).to_bytes();
+		let transcript = transcript(&client_ephemeral, &server_ephemeral, &self.server_public_key);
+		let mut keys = SessionKeys::derive(shared_secrets, &transcript, true);
+		// NOTE: Forged welcomes are ignored instead of failing, so they can't break the connection
+		if !keys.verify_welcome_tag(&transcript, &welcome[1 + KEY_SIZE..]) {
+			return;
+		}
+
+		let ClientHandshake::Pending { queued_msgs, early_msgs, .. } = std::mem::replace(&mut self.handshake, ClientHandshake::Failed) else {
+			unreachable!();
+		};
+		for (channel_id, data) in queued_msgs {
+			let packet = keys.encrypt(&data, channel_id);
+			// NOTE: Errors of queued msgs can't be reported anymore, the transport reports a broken connection on its own
+			let _ = self.transport.send(&packet, channel_id);
+		}
+		for (channel_id, packet) in early_msgs {
+			if let Some(data) = keys.decrypt(&packet, channel_id) {
+				self.events.push_back(ClientTransportEvent::NewMsg(channel_id, data));
+			}
+		}
+		self.handshake = ClientHandshake::Done(keys);
+	}
+
+	fn handle_transport_event(&mut self, event: ClientTransportEvent) {
+		match event {
+			ClientTransportEvent::NewMsg(channel_id, data) => {
+				match (data.first(), &mut self.handshake) {
+					(Some(&WELCOME), _) => self.handle_welcome(&data),
+					(Some(&DATA), ClientHandshake::Done(keys)) => {
+						if let Some(data) = keys.decrypt(&data, channel_id) {
+							self.events.push_back(ClientTransportEvent::NewMsg(channel_id, data));
+						}
+					},
+					(Some(&DATA), ClientHandshake::Pending { early_msgs, .. }) if early_msgs.len() < MAX_EARLY_MSGS => {
+						early_msgs.push((channel_id, data));
+					},
+					_ => {},
+				}
+			},
+			ClientTransportEvent::ConnectionRejected(_) | ClientTransportEvent::ServerDisconnected(_) => {
+				self.handshake = ClientHandshake::Failed;
+				self.events.push_back(event);
+			},
+			event => self.events.push_back(event),
+		}
+	}
+}
+
+impl<T: ClientTransport> ClientTransport for EncryptedClientTransport<T> {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		loop {
+			self.update_handshake();
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			let event = self.transport.receive_event()?;
+			self.handle_transport_event(event);
+		}
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			self.update_handshake();
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+
+			let now = Instant::now();
+			if deadline.is_some_and(|deadline| deadline <= now) {
+				return None;
+			}
+			// wakes up in time to resend the hello
+			let wake_up = match &self.handshake {
+				ClientHandshake::Pending { last_hello, .. } => deadline.into_iter().chain([*last_hello + HANDSHAKE_RESEND_INTERVAL]).min(),
+				_ => deadline,
+			};
+			match self.transport.wait_event(wake_up.map(|wake_up| wake_up.saturating_duration_since(now))) {
+				Some(event) => self.handle_transport_event(event),
+				// the transport shut down
+				None if wake_up.is_none_or(|wake_up| Instant::now() < wake_up) => return None,
+				None => {},
+			}
+		}
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		match &mut self.handshake {
+			ClientHandshake::Pending { queued_msgs, .. } => {
+				queued_msgs.push((channel_id, data.to_vec()));
+				Ok(())
+			},
+			ClientHandshake::Done(keys) => {
+				let packet = keys.encrypt(data, channel_id);
+				self.transport.send(&packet, channel_id)
+			},
+			ClientHandshake::Failed => Err(SendError::Disconnected),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
+
+	fn is_reliable(&self, channel_id: ChannelId) -> bool {
+		self.transport.is_reliable(channel_id)
+	}
+}
@@ -0,0 +1,4 @@
+// NOTE: Both work as wrappers, tls around tcp and the datagram encryption around udp.
+// Tls protects the whole stream, the datagram encryption only the msgs and not the udp packets carrying them.
+pub mod tls;
+pub mod datagram;
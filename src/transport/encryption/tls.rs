@@ -0,0 +1,443 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+use crate::transport::{DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_MSG_SIZE, ChannelId, ClientTransport, ClientTransportEvent, ConnectionRejectedReason, DisconnectReason, SendError, ServerTransport, ServerTransportEvent, TransportMsg};
+
+// NOTE: The tls records form one stream, so they all go over this channel of the wrapped transport,
+// which has to be reliable and ordered like every tcp channel. The actual channel is part of the encrypted frame.
+const TLS_CHANNEL: ChannelId = 0;
+// how much of the tls stream goes into one msg of the wrapped transport
+const TLS_CHUNK_SIZE: usize = 16 * 1024;
+// length and channel id in front of every msg inside the tls stream
+const FRAME_HEADER_SIZE: usize = 5;
+
+pub struct SelfSignedCert {
+	pub cert: CertificateDer<'static>,
+	pub key: PrivateKeyDer<'static>,
+}
+
+// for local testing, clients have to trust the cert explicitly with client_config
+pub fn self_signed_cert(subject_alt_names: impl Into<Vec<String>>) -> Result<SelfSignedCert, rcgen::Error> {
+	let certified_key = rcgen::generate_simple_self_signed(subject_alt_names)?;
+	Ok(SelfSignedCert {
+		cert: certified_key.cert.der().clone(),
+		key: PrivateKeyDer::Pkcs8(certified_key.key_pair.serialize_der().into()),
+	})
+}
+
+pub fn server_config(cert_chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Result<Arc<ServerConfig>, rustls::Error> {
+	let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+		.with_safe_default_protocol_versions()?
+		.with_no_client_auth()
+		.with_single_cert(cert_chain, key)?;
+	Ok(Arc::new(config))
+}
+
+// trusts only the given certs, e.g. the one of self_signed_cert
+pub fn client_config(trusted_certs: impl IntoIterator<Item = CertificateDer<'static>>) -> Result<Arc<ClientConfig>, rustls::Error> {
+	let mut root_store = RootCertStore::empty();
+	for cert in trusted_certs {
+		root_store.add(cert)?;
+	}
+	let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+		.with_safe_default_protocol_versions()?
+		.with_root_certificates(root_store)
+		.with_no_client_auth();
+	Ok(Arc::new(config))
+}
+
+fn frame_msg(data: &[u8], channel_id: ChannelId, max_msg_size: usize) -> Result<Vec<u8>, SendError> {
+	if data.len() > max_msg_size {
+		return Err(SendError::MsgTooLarge {
+			size: data.len(),
+			max_size: max_msg_size,
+		});
+	}
+	let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + data.len());
+	frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	frame.push(channel_id);
+	frame.extend_from_slice(data);
+	Ok(frame)
+}
+
+struct TlsSession {
+	connection: Connection,
+	// decrypted bytes that don't form a whole msg yet
+	plaintext: Vec<u8>,
+	max_msg_size: usize,
+	started: Instant,
+}
+
+impl TlsSession {
+	fn new(connection: impl Into<Connection>, max_msg_size: usize) -> Self {
+		let mut connection = connection.into();
+		// whole msgs get written at once, the output is taken right after
+		connection.set_buffer_limit(None);
+		Self {
+			connection,
+			plaintext: Vec::new(),
+			max_msg_size,
+			started: Instant::now(),
+		}
+	}
+
+	// decrypts a chunk of the tls stream and returns the msgs it completed
+	fn receive(&mut self, mut data: &[u8]) -> io::Result<Vec<(ChannelId, Vec<u8>)>> {
+		let mut buffer = [0; 4096];
+		while !data.is_empty() {
+			self.connection.read_tls(&mut data)?;
+			self.connection.process_new_packets().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+			loop {
+				match self.connection.reader().read(&mut buffer) {
+					// the other side closed the tls session, the wrapped transport reports the disconnect
+					Ok(0) => break,
+					Ok(read) => self.plaintext.extend_from_slice(&buffer[..read]),
+					Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+					Err(e) => return Err(e),
+				}
+			}
+		}
+
+		let mut msgs = Vec::new();
+		let mut start = 0;
+		while self.plaintext.len() - start >= FRAME_HEADER_SIZE {
+			let size = u32::from_le_bytes(self.plaintext[start..start + 4].try_into().unwrap()) as usize;
+			if size > self.max_msg_size {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("received msg of {size} bytes, but the max size is {}", self.max_msg_size)));
+			}
+			let end = start + FRAME_HEADER_SIZE + size;
+			if self.plaintext.len() < end {
+				break;
+			}
+			msgs.push((self.plaintext[start + 4], self.plaintext[start + FRAME_HEADER_SIZE..end].to_vec()));
+			start = end;
+		}
+		self.plaintext.drain(..start);
+		Ok(msgs)
+	}
+
+	// NOTE: Msgs written during the handshake are buffered by rustls until it is done
+	fn write(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let frame = frame_msg(data, channel_id, self.max_msg_size)?;
+		self.connection.writer().write_all(&frame)?;
+		Ok(())
+	}
+
+	// the tls records that have to be sent to the other side
+	fn take_output(&mut self) -> Vec<u8> {
+		let mut output = Vec::new();
+		while self.connection.wants_write() {
+			// writing into a vec can't fail
+			self.connection.write_tls(&mut output).unwrap();
+		}
+		output
+	}
+}
+
+// wraps a transport with reliable ordered msgs, like tcp, into tls
+pub struct TlsServerTransport<T: ServerTransport> {
+	transport: T,
+	config: Arc<ServerConfig>,
+	sessions: HashMap<SocketAddr, (TlsSession, bool)>,
+	events: VecDeque<ServerTransportEvent>,
+	max_msg_size: usize,
+	max_pending_handshakes: Option<usize>,
+	handshake_timeout: Option<Duration>,
+}
+
+impl<T: ServerTransport> TlsServerTransport<T> {
+	pub fn new(transport: T, config: Arc<ServerConfig>) -> Self {
+		Self {
+			transport,
+			config,
+			sessions: HashMap::new(),
+			events: VecDeque::new(),
+			max_msg_size: DEFAULT_MAX_MSG_SIZE,
+			max_pending_handshakes: None,
+			handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+		}
+	}
+
+	// limits the decrypted msgs, the default is the same as the one of the transports
+	pub fn with_max_msg_size(mut self, max_msg_size: usize) -> Self {
+		self.max_msg_size = max_msg_size;
+		self
+	}
+
+	fn expire_handshakes(&mut self) {
+		let Some(handshake_timeout) = self.handshake_timeout else {
+			return;
+		};
+		let now = Instant::now();
+		let expired_addresses: Vec<SocketAddr> = self.sessions.iter()
+			.filter(|(_address, (session, established))| !established && now.duration_since(session.started) >= handshake_timeout)
+			.map(|(address, _session)| *address)
+			.collect();
+		for address in expired_addresses {
+			self.sessions.remove(&address);
+			let reason = ConnectionRejectedReason::HandshakeFailed("the handshake timed out".to_string());
+			self.transport.disconnect(address, DisconnectReason::Rejected(reason.clone()));
+			self.events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+		}
+	}
+
+	fn next_handshake_expiry(&self) -> Option<Instant> {
+		let handshake_timeout = self.handshake_timeout?;
+		self.sessions.values()
+			.filter(|(_session, established)| !established)
+			.map(|(session, _established)| session.started + handshake_timeout)
+			.min()
+	}
+
+	fn send_output(transport: &mut T, address: SocketAddr, session: &mut TlsSession) -> Result<(), SendError> {
+		let output = session.take_output();
+		for chunk in output.chunks(TLS_CHUNK_SIZE) {
+			transport.send(address, chunk, TLS_CHANNEL)?;
+		}
+		Ok(())
+	}
+
+	fn handle_transport_event(&mut self, event: ServerTransportEvent) {
+		match event {
+			// the client only counts as connected once the handshake is done
+			ServerTransportEvent::NewClient(address) => {
+				let pending_handshakes = self.sessions.values().filter(|(_, established)| !established).count();
+				if self.max_pending_handshakes.is_some_and(|max_pending| pending_handshakes >= max_pending) {
+					let reason = ConnectionRejectedReason::TooManyPendingHandshakes;
+					self.transport.disconnect(address, DisconnectReason::Rejected(reason.clone()));
+					self.events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+					return;
+				}
+				match ServerConnection::new(self.config.clone()) {
+					Ok(connection) => {
+						self.sessions.insert(address, (TlsSession::new(connection, self.max_msg_size), false));
+					},
+					Err(e) => {
+						self.transport.disconnect(address, DisconnectReason::ConnectionLost);
+						self.events.push_back(ServerTransportEvent::FailedToAcceptConnection(io::Error::other(e)));
+					},
+				}
+			},
+			ServerTransportEvent::ClientDisconnected(address, reason) => {
+				if let Some((_, true)) = self.sessions.remove(&address) {
+					self.events.push_back(ServerTransportEvent::ClientDisconnected(address, reason));
+				}
+			},
+			ServerTransportEvent::NewMsg(msg) => {
+				let address = msg.sender_address;
+				let Some((session, established)) = self.sessions.get_mut(&address) else {
+					return;
+				};
+				let result = session.receive(&msg.data);
+				// also delivers the alert if the session failed
+				let _ = Self::send_output(&mut self.transport, address, session);
+				match result {
+					Ok(msgs) => {
+						if !*established && !session.connection.is_handshaking() {
+							*established = true;
+							self.events.push_back(ServerTransportEvent::NewClient(address));
+						}
+						for (channel_id, data) in msgs {
+							self.events.push_back(ServerTransportEvent::NewMsg(TransportMsg {
+								sender_address: address,
+								channel_id,
+								data,
+							}));
+						}
+					},
+					Err(e) => {
+						let established = *established;
+						self.sessions.remove(&address);
+						self.transport.disconnect(address, DisconnectReason::ConnectionLost);
+						if established {
+							self.events.push_back(ServerTransportEvent::ClientDisconnected(address, DisconnectReason::ConnectionLost));
+						}
+						else {
+							self.events.push_back(ServerTransportEvent::FailedToAcceptConnection(e));
+						}
+					},
+				}
+			},
+			event => self.events.push_back(event),
+		}
+	}
+}
+
+impl<T: ServerTransport> ServerTransport for TlsServerTransport<T> {
+	fn receive_event(&mut self) -> Option<ServerTransportEvent> {
+		self.expire_handshakes();
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			let event = self.transport.receive_event()?;
+			self.handle_transport_event(event);
+		}
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			self.expire_handshakes();
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			// also wakes up for the next handshake that times out
+			let wake_up = deadline.into_iter().chain(self.next_handshake_expiry()).min();
+			let now = Instant::now();
+			match self.transport.wait_event(wake_up.map(|wake_up| wake_up.saturating_duration_since(now))) {
+				Some(event) => self.handle_transport_event(event),
+				None => {
+					let now = Instant::now();
+					// returning early means the transport shut down
+					if deadline.is_some_and(|deadline| deadline <= now) || wake_up.is_none_or(|wake_up| now < wake_up) {
+						return None;
+					}
+				},
+			}
+		}
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let Some((session, true)) = self.sessions.get_mut(&address) else {
+			return Err(SendError::Disconnected);
+		};
+		session.write(data, channel_id)?;
+		Self::send_output(&mut self.transport, address, session)
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		if let Some((mut session, _)) = self.sessions.remove(&address) {
+			session.connection.send_close_notify();
+			let _ = Self::send_output(&mut self.transport, address, &mut session);
+		}
+		self.transport.disconnect(address, reason);
+	}
+
+	// the tls handshakes count as pending until they are done
+	fn apply_config(&mut self, config: &crate::transport::ServerConfig) {
+		self.max_pending_handshakes = config.max_pending_handshakes;
+		self.handshake_timeout = config.handshake_timeout;
+		self.transport.apply_config(config);
+	}
+}
+
+pub struct TlsClientTransport<T: ClientTransport> {
+	transport: T,
+	session: TlsSession,
+	events: VecDeque<ClientTransportEvent>,
+	disconnected: bool,
+}
+
+impl<T: ClientTransport> TlsClientTransport<T> {
+	// the server has to present a cert for server_name that the config trusts
+	pub fn new(transport: T, config: Arc<ClientConfig>, server_name: &str) -> io::Result<Self> {
+		Self::with_max_msg_size(transport, config, server_name, DEFAULT_MAX_MSG_SIZE)
+	}
+
+	pub fn with_max_msg_size(transport: T, config: Arc<ClientConfig>, server_name: &str, max_msg_size: usize) -> io::Result<Self> {
+		let server_name = ServerName::try_from(server_name.to_string()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+		let connection = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+		let mut client = Self {
+			transport,
+			session: TlsSession::new(connection, max_msg_size),
+			events: VecDeque::new(),
+			disconnected: false,
+		};
+		client.send_output().map_err(io::Error::other)?;
+		Ok(client)
+	}
+
+	fn send_output(&mut self) -> Result<(), SendError> {
+		let output = self.session.take_output();
+		for chunk in output.chunks(TLS_CHUNK_SIZE) {
+			self.transport.send(chunk, TLS_CHANNEL)?;
+		}
+		Ok(())
+	}
+
+	fn handle_transport_event(&mut self, event: ClientTransportEvent) {
+		match event {
+			ClientTransportEvent::NewMsg(_, data) => {
+				if self.disconnected {
+					return;
+				}
+				let handshaking = self.session.connection.is_handshaking();
+				let result = self.session.receive(&data);
+				let _ = self.send_output();
+				match result {
+					Ok(msgs) => {
+						for (channel_id, data) in msgs {
+							self.events.push_back(ClientTransportEvent::NewMsg(channel_id, data));
+						}
+					},
+					Err(e) => {
+						self.disconnected = true;
+						if handshaking {
+							self.events.push_back(ClientTransportEvent::ConnectionRejected(ConnectionRejectedReason::HandshakeFailed(e.to_string())));
+						}
+						else {
+							self.events.push_back(ClientTransportEvent::ServerDisconnected(DisconnectReason::ConnectionLost));
+						}
+					},
+				}
+			},
+			ClientTransportEvent::ConnectionRejected(_) | ClientTransportEvent::ServerDisconnected(_) => {
+				// the session already failed on its own
+				if !self.disconnected {
+					self.disconnected = true;
+					self.events.push_back(event);
+				}
+			},
+			event => self.events.push_back(event),
+		}
+	}
+}
+
+impl<T: ClientTransport> ClientTransport for TlsClientTransport<T> {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			let event = self.transport.receive_event()?;
+			self.handle_transport_event(event);
+		}
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Some(event);
+			}
+			let event = self.transport.wait_event(deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())))?;
+			self.handle_transport_event(event);
+		}
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		if self.disconnected {
+			return Err(SendError::Disconnected);
+		}
+		self.session.write(data, channel_id)?;
+		self.send_output()
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		self.transport.flush()
+	}
+
+	// every channel shares the tls stream
+	fn is_reliable(&self, _channel_id: ChannelId) -> bool {
+		self.transport.is_reliable(TLS_CHANNEL)
+	}
+}
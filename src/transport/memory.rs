@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::transport::{ChannelId, ClientTransport, ClientTransportEvent, DisconnectReason, SendError, ServerTransport, ServerTransportEvent, TransportMsg, wait_on_channel};
+
+// NOTE: There are no threads or sockets involved, msgs are handed over through channels as soon as they are sent.
+// So a whole session is deterministic when server and clients are driven from a single thread,
+// but each end can just as well be moved to its own thread.
+
+enum ClientToServerMsg {
+	Connect(SocketAddr, Sender<ClientTransportEvent>),
+	Msg(SocketAddr, ChannelId, Vec<u8>),
+	Disconnect(SocketAddr),
+}
+
+pub struct MemoryServerTransport {
+	sender: Sender<ClientToServerMsg>,
+	receiver: Receiver<ClientToServerMsg>,
+	clients: HashMap<SocketAddr, Sender<ClientTransportEvent>>,
+	next_client_id: Arc<AtomicU64>,
+}
+
+impl Default for MemoryServerTransport {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MemoryServerTransport {
+	pub fn new() -> Self {
+		let (sender, receiver) = std::sync::mpsc::channel();
+		Self {
+			sender,
+			receiver,
+			clients: HashMap::new(),
+			next_client_id: Arc::default(),
+		}
+	}
+
+	// stays usable after the transport got moved into a Server
+	pub fn connector(&self) -> MemoryConnector {
+		MemoryConnector {
+			sender: self.sender.clone(),
+			next_client_id: self.next_client_id.clone(),
+		}
+	}
+
+	// returns None for msgs of clients that got kicked in the meantime
+	fn handle_client_msg(&mut self, msg: ClientToServerMsg) -> Option<ServerTransportEvent> {
+		match msg {
+			ClientToServerMsg::Connect(address, sender) => {
+				self.clients.insert(address, sender);
+				Some(ServerTransportEvent::NewClient(address))
+			},
+			ClientToServerMsg::Msg(address, channel_id, data) => {
+				if !self.clients.contains_key(&address) {
+					return None;
+				}
+				Some(ServerTransportEvent::NewMsg(TransportMsg {
+					sender_address: address,
+					channel_id,
+					data,
+				}))
+			},
+			ClientToServerMsg::Disconnect(address) => {
+				self.clients.remove(&address)?;
+				Some(ServerTransportEvent::ClientDisconnected(address, DisconnectReason::Closed))
+			},
+		}
+	}
+}
+
+impl Drop for MemoryServerTransport {
+	fn drop(&mut self) {
+		for sender in self.clients.values() {
+			let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::Closed));
+		}
+	}
+}
+
+impl ServerTransport for MemoryServerTransport {
+	fn receive_event(&mut self) -> Option<ServerTransportEvent> {
+		loop {
+			let msg = self.receiver.try_recv().ok()?;
+			if let Some(event) = self.handle_client_msg(msg) {
+				return Some(event);
+			}
+		}
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			let msg = match deadline {
+				Some(deadline) => {
+					match self.receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+						Ok(msg) => msg,
+						Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return None,
+					}
+				},
+				// the transport holds a sender itself, so this never ends on its own
+				None => self.receiver.recv().ok()?,
+			};
+			if let Some(event) = self.handle_client_msg(msg) {
+				return Some(event);
+			}
+		}
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let sender = self.clients.get(&address).ok_or(SendError::Disconnected)?;
+		sender.send(ClientTransportEvent::NewMsg(channel_id, data.to_vec())).map_err(|_| SendError::Disconnected)
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		if let Some(sender) = self.clients.remove(&address) {
+			let _ = sender.send(ClientTransportEvent::ServerDisconnected(reason));
+		}
+	}
+}
+
+// hands out clients that are connected to the server transport it came from
+#[derive(Clone)]
+pub struct MemoryConnector {
+	sender: Sender<ClientToServerMsg>,
+	next_client_id: Arc<AtomicU64>,
+}
+
+impl MemoryConnector {
+	// Every client gets its own made up address. The server sees it as a new client on its next receive.
+	pub fn connect(&self) -> MemoryClientTransport {
+		let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+		let address = SocketAddr::from((Ipv6Addr::from(client_id as u128), 0));
+		let (sender, receiver) = std::sync::mpsc::channel();
+		// the server already got dropped, which the client notices on its first send
+		let _ = self.sender.send(ClientToServerMsg::Connect(address, sender));
+
+		MemoryClientTransport {
+			address,
+			server_sender: self.sender.clone(),
+			event_receiver: receiver,
+			server_disconnected: false,
+		}
+	}
+}
+
+pub struct MemoryClientTransport {
+	address: SocketAddr,
+	server_sender: Sender<ClientToServerMsg>,
+	event_receiver: Receiver<ClientTransportEvent>,
+	server_disconnected: bool,
+}
+
+impl MemoryClientTransport {
+	// the address the server knows this client by
+	pub fn address(&self) -> SocketAddr {
+		self.address
+	}
+
+	fn track_disconnect(&mut self, event: Option<ClientTransportEvent>) -> Option<ClientTransportEvent> {
+		if let Some(ClientTransportEvent::ServerDisconnected(_)) = &event {
+			self.server_disconnected = true;
+		}
+		event
+	}
+}
+
+impl Drop for MemoryClientTransport {
+	fn drop(&mut self) {
+		if !self.server_disconnected {
+			let _ = self.server_sender.send(ClientToServerMsg::Disconnect(self.address));
+		}
+	}
+}
+
+impl ClientTransport for MemoryClientTransport {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		let event = self.event_receiver.try_recv().ok();
+		self.track_disconnect(event)
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		let event = wait_on_channel(&self.event_receiver, timeout);
+		self.track_disconnect(event)
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		if self.server_disconnected {
+			return Err(SendError::Disconnected);
+		}
+		self.server_sender.send(ClientToServerMsg::Msg(self.address, channel_id, data.to_vec())).map_err(|_| SendError::Disconnected)
+	}
+}
@@ -1,38 +1,308 @@
 use std::net::SocketAddr;
 use std::io;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::codec::CodecError;
 
 // NOTE: Changing this is not recommended, since it
 pub const MAX_MSG_SIZE: usize = 65507;
+// default cap of the built in transports for a single msg, which may span many datagrams
+pub const DEFAULT_MAX_MSG_SIZE: usize = 1024 * 1024;
+// default of ServerConfig::handshake_timeout, clients give up on their own handshakes after it as well
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// how often the default wait_event implementations check for new events
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(1);
 
 pub mod tcp;
 pub mod udp;
 pub mod simulator;
+pub mod memory;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+
+pub type ChannelId = u8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryMode {
+	// may be lost, duplicated or arrive out of order
+	Unreliable,
+	// may be lost, but msgs older than the newest received one are dropped
+	UnreliableSequenced,
+	// is guaranteed to arrive, but may overtake other msgs
+	ReliableUnordered,
+	// is guaranteed to arrive in the order it was sent in
+	ReliableOrdered,
+}
+
+// Lets the transports queue msgs and send them together, so that many small msgs share a datagram or write.
+// The receiving side unpacks them again, so it doesn't need to be configured the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coalescing {
+	// every msg gets sent right away
+	Disabled,
+	// msgs wait until flush gets called
+	Manual,
+	// msgs wait until flush gets called, but at most this long
+	Interval(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionRejectedReason {
+	// client and server use incompatible versions of this crate
+	TransportVersionMismatch,
+	ApplicationIdMismatch,
+	ProtocolVersionMismatch {
+		server_version: u32,
+		client_version: u32,
+	},
+	// the server couldn't be authenticated or the encryption couldn't be set up
+	HandshakeFailed(String),
+	// the server didn't admit the client, e.g. because its connect payload was invalid
+	Rejected(String),
+	// the server already has as many clients as its config allows
+	ServerFull,
+	TooManyPendingHandshakes,
+}
+
+impl std::fmt::Display for ConnectionRejectedReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::TransportVersionMismatch => write!(f, "client and server use incompatible transport versions"),
+			Self::ApplicationIdMismatch => write!(f, "client and server belong to different applications"),
+			Self::ProtocolVersionMismatch { server_version, client_version } => {
+				write!(f, "server uses protocol version {server_version}, but client uses {client_version}")
+			},
+			Self::HandshakeFailed(e) => write!(f, "the encryption handshake failed: {e}"),
+			Self::Rejected(reason) => write!(f, "the server didn't admit the client: {reason}"),
+			Self::ServerFull => write!(f, "the server is full"),
+			Self::TooManyPendingHandshakes => write!(f, "the server has too many pending handshakes"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+	// the other side closed the connection
+	Closed,
+	// the connection broke, e.g. because it got reset or received corrupted data
+	ConnectionLost,
+	// nothing was received for longer than the idle timeout
+	TimedOut,
+	// the server disconnected the client on purpose
+	Kicked(String),
+	// the server turned the client away before admitting it, the client sees this as a rejected connection
+	Rejected(ConnectionRejectedReason),
+	// the client sent more msgs or bytes per second than the server allows
+	RateLimited,
+	// the client sent a msg over the max msg size of the server
+	MsgTooLarge {
+		size: usize,
+		max_size: usize,
+	},
+}
+
+impl std::fmt::Display for DisconnectReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Closed => write!(f, "connection closed"),
+			Self::ConnectionLost => write!(f, "connection lost"),
+			Self::TimedOut => write!(f, "timed out"),
+			Self::Kicked(reason) => write!(f, "kicked: {reason}"),
+			Self::Rejected(reason) => write!(f, "rejected: {reason}"),
+			Self::RateLimited => write!(f, "exceeded the rate limit"),
+			Self::MsgTooLarge { size, max_size } => write!(f, "sent a msg of {size} bytes, which is over the maximum of {max_size} bytes"),
+		}
+	}
+}
+
+// Limits that protect the server against misbehaving clients, None means unlimited.
+// Transports enforce what they can before a client exists, the server front end enforces the rest.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+	// connections over it get rejected with ServerFull
+	pub max_clients: Option<usize>,
+	// handshakes of the transport and clients that didn't send their connect payload yet
+	pub max_pending_handshakes: Option<usize>,
+	// clients that send larger msgs get kicked
+	pub max_msg_size: Option<usize>,
+	// clients that send more within a second get kicked
+	pub max_msgs_per_second: Option<u32>,
+	pub max_bytes_per_second: Option<usize>,
+	// connections that are still pending after it get rejected, so that they don't hold on to their slot forever
+	pub handshake_timeout: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		Self {
+			max_clients: None,
+			max_pending_handshakes: None,
+			max_msg_size: None,
+			max_msgs_per_second: None,
+			max_bytes_per_second: None,
+			handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum SendError {
+	FailedToSerialize(CodecError),
+	// the msg is larger than what the transport is able to send
+	MsgTooLarge {
+		size: usize,
+		max_size: usize,
+	},
+	// the receiver isn't connected (anymore)
+	Disconnected,
+	// the transport isn't configured with this channel
+	InvalidChannel {
+		channel_id: ChannelId,
+		channel_count: usize,
+	},
+	// the msg has to arrive, but the transport may lose the msgs of this channel
+	UnreliableChannel(ChannelId),
+	Io(io::Error),
+}
+
+impl std::fmt::Display for SendError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::FailedToSerialize(e) => write!(f, "failed to serialize msg: {e}"),
+			Self::MsgTooLarge { size, max_size } => write!(f, "msg of {size} bytes is over the maximum of {max_size} bytes"),
+			Self::Disconnected => write!(f, "not connected"),
+			Self::InvalidChannel { channel_id, channel_count } => write!(f, "can't send on channel {channel_id}, only {channel_count} channels are configured"),
+			Self::UnreliableChannel(channel_id) => write!(f, "can't send on channel {channel_id}, it isn't reliable"),
+			Self::Io(e) => write!(f, "failed to send msg: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for SendError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::FailedToSerialize(e) => Some(e),
+			Self::Io(e) => Some(e),
+			Self::MsgTooLarge { .. } | Self::Disconnected | Self::InvalidChannel { .. } | Self::UnreliableChannel(_) => None,
+		}
+	}
+}
+
+impl From<io::Error> for SendError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+pub(crate) fn check_channel(channel_id: ChannelId, channel_count: usize) -> Result<(), SendError> {
+	if channel_id as usize >= channel_count {
+		return Err(SendError::InvalidChannel {
+			channel_id,
+			channel_count,
+		});
+	}
+	Ok(())
+}
 
 pub struct TransportMsg {
 	pub sender_address: SocketAddr,
+	pub channel_id: ChannelId,
 	pub data: Vec<u8>,
 }
 
 pub enum ClientTransportEvent {
-	NewMsg(Vec<u8>),
+	NewMsg(ChannelId, Vec<u8>),
 	FailedToReceiveMsg(io::Error),
-	ServerDisconnected,
+	ConnectionRejected(ConnectionRejectedReason),
+	ServerDisconnected(DisconnectReason),
 }
 
 pub enum ServerTransportEvent {
 	NewClient(SocketAddr),
-	ClientDisconnected(SocketAddr),
+	ClientDisconnected(SocketAddr, DisconnectReason),
 	FailedToReceiveMsg(io::Error),
 	NewMsg(TransportMsg),
 	FailedToAcceptConnection(io::Error),
+	// the transport turned the connection away before it became a client
+	ClientRejected(SocketAddr, ConnectionRejectedReason),
+}
+
+// waits forever if there is no timeout, returns None once the channel is closed
+pub(crate) fn wait_on_channel<T>(receiver: &Receiver<T>, timeout: Option<Duration>) -> Option<T> {
+	match timeout {
+		Some(timeout) => receiver.recv_timeout(timeout).ok(),
+		None => receiver.recv().ok(),
+	}
 }
 
 pub trait ServerTransport {
 	fn receive_event(&mut self) -> Option<ServerTransportEvent>;
-	fn send(&mut self, address: SocketAddr, data: &[u8]);
+	// blocks until an event arrives or the timeout runs out, None waits forever
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if let Some(event) = self.receive_event() {
+				return Some(event);
+			}
+			if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+				return None;
+			}
+			std::thread::sleep(WAIT_POLL_INTERVAL);
+		}
+	}
+	// fails with Disconnected if there is no client with this address
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError>;
+	// Addresses without a client are skipped, the first other error is returned after trying all addresses.
+	// Transports that can batch sends to multiple addresses should override this.
+	fn send_to_many(&mut self, addresses: &[SocketAddr], data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let mut result = Ok(());
+		for address in addresses {
+			match self.send(*address, data, channel_id) {
+				Ok(()) | Err(SendError::Disconnected) => {},
+				Err(e) => {
+					if result.is_ok() {
+						result = Err(e);
+					}
+				},
+			}
+		}
+		result
+	}
+	// sends the msgs that coalescing transports queued, transports that send right away have nothing to do
+	fn flush(&mut self) -> Result<(), SendError> {
+		Ok(())
+	}
+	// the client gets notified with the reason, no ClientDisconnected event is emitted for it
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason);
+	// transports that can turn connections away before they become clients should override this
+	fn apply_config(&mut self, _config: &ServerConfig) {}
 }
 
 pub trait ClientTransport {
 	fn receive_event(&mut self) -> Option<ClientTransportEvent>;
-	fn send(&mut self, data: &[u8]);
+	// blocks until an event arrives or the timeout runs out, None waits forever
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if let Some(event) = self.receive_event() {
+				return Some(event);
+			}
+			if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+				return None;
+			}
+			std::thread::sleep(WAIT_POLL_INTERVAL);
+		}
+	}
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError>;
+	// sends the msgs that coalescing transports queued, transports that send right away have nothing to do
+	fn flush(&mut self) -> Result<(), SendError> {
+		Ok(())
+	}
+	// whether msgs of the channel are guaranteed to arrive, streams like tcp deliver every channel reliably
+	fn is_reliable(&self, _channel_id: ChannelId) -> bool {
+		true
+	}
 }
@@ -0,0 +1,45 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// where the simulator takes the time from, to decide when packets are due
+pub trait Clock: Send + Sync {
+	fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+}
+
+// only moves on when told to, clones share the same time
+#[derive(Clone)]
+pub struct ManualClock {
+	now: Arc<Mutex<Instant>>,
+}
+
+impl Default for ManualClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ManualClock {
+	pub fn new() -> Self {
+		Self {
+			now: Arc::new(Mutex::new(Instant::now())),
+		}
+	}
+
+	pub fn advance(&self, duration: Duration) {
+		*self.now.lock().unwrap() += duration;
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Instant {
+		*self.now.lock().unwrap()
+	}
+}
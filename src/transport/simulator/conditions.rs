@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+use rand::Rng;
+use rand::rngs::StdRng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Jitter {
+	#[default]
+	None,
+	// evenly spread between -max and +max
+	Uniform(Duration),
+	// normal distribution with this standard deviation
+	Normal(Duration),
+}
+
+// Gilbert-Elliott model, the link switches between a good and a bad state after every packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurstLoss {
+	pub good_to_bad_percentage: f32,
+	pub bad_to_good_percentage: f32,
+	pub good_loss_percentage: f32,
+	pub bad_loss_percentage: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PacketLoss {
+	#[default]
+	None,
+	// every packet is lost with the same chance
+	Random(f32),
+	Bursts(BurstLoss),
+}
+
+// NOTE: All percentages are chances between 0.0 and 1.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConditions {
+	pub latency: Duration,
+	pub jitter: Jitter,
+	pub loss: PacketLoss,
+	// reordered packets skip the latency and overtake the ones in flight
+	pub reorder_percentage: f32,
+	pub duplicate_percentage: f32,
+	// flips a single bit of the packet
+	pub corruption_percentage: f32,
+	// bytes per second, packets wait in a queue while the link is busy
+	pub bandwidth: Option<u32>,
+	// packets that don't fit into the queue anymore are dropped
+	pub max_queued_bytes: usize,
+}
+
+impl Default for NetworkConditions {
+	fn default() -> Self {
+		Self {
+			latency: Duration::ZERO,
+			jitter: Jitter::None,
+			loss: PacketLoss::None,
+			reorder_percentage: 0.0,
+			duplicate_percentage: 0.0,
+			corruption_percentage: 0.0,
+			bandwidth: None,
+			max_queued_bytes: 64 * 1024,
+		}
+	}
+}
+
+impl NetworkConditions {
+	pub fn lan() -> Self {
+		Self {
+			latency: Duration::from_micros(500),
+			jitter: Jitter::Uniform(Duration::from_micros(200)),
+			bandwidth: Some(125_000_000),
+			max_queued_bytes: 1024 * 1024,
+			..Default::default()
+		}
+	}
+
+	pub fn bad_wifi() -> Self {
+		Self {
+			latency: Duration::from_millis(20),
+			jitter: Jitter::Normal(Duration::from_millis(15)),
+			loss: PacketLoss::Bursts(BurstLoss {
+				good_to_bad_percentage: 0.02,
+				bad_to_good_percentage: 0.25,
+				good_loss_percentage: 0.005,
+				bad_loss_percentage: 0.5,
+			}),
+			reorder_percentage: 0.01,
+			duplicate_percentage: 0.005,
+			corruption_percentage: 0.001,
+			bandwidth: Some(2_500_000),
+			max_queued_bytes: 128 * 1024,
+		}
+	}
+
+	pub fn mobile_3g() -> Self {
+		Self {
+			latency: Duration::from_millis(120),
+			jitter: Jitter::Normal(Duration::from_millis(30)),
+			loss: PacketLoss::Bursts(BurstLoss {
+				good_to_bad_percentage: 0.01,
+				bad_to_good_percentage: 0.3,
+				good_loss_percentage: 0.01,
+				bad_loss_percentage: 0.3,
+			}),
+			reorder_percentage: 0.005,
+			duplicate_percentage: 0.0,
+			corruption_percentage: 0.0,
+			bandwidth: Some(96_000),
+			max_queued_bytes: 32 * 1024,
+		}
+	}
+
+	fn delay(&self, rng: &mut StdRng) -> Duration {
+		let jitter = match self.jitter {
+			Jitter::None => 0.0,
+			Jitter::Uniform(max) => (rng.gen::<f64>() * 2.0 - 1.0) * max.as_secs_f64(),
+			Jitter::Normal(std_dev) => {
+				// Box-Muller transform
+				let u1 = 1.0 - rng.gen::<f64>();
+				let u2 = rng.gen::<f64>();
+				(-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos() * std_dev.as_secs_f64()
+			},
+		};
+		Duration::from_secs_f64((self.latency.as_secs_f64() + jitter).max(0.0))
+	}
+}
+
+fn chance(rng: &mut StdRng, percentage: f32) -> bool {
+	percentage > 0.0 && rng.gen::<f32>() < percentage
+}
+
+// the state of one direction between two endpoints
+#[derive(Debug)]
+pub(crate) struct Link {
+	in_bad_state: bool,
+	// when the bandwidth limited link is done sending the queued packets
+	busy_until: Instant,
+	// packets don't overtake each other unless they get reordered
+	last_delivery: Instant,
+}
+
+impl Link {
+	pub(crate) fn new(now: Instant) -> Self {
+		Self {
+			in_bad_state: false,
+			busy_until: now,
+			last_delivery: now,
+		}
+	}
+
+	fn is_lost(&mut self, loss: &PacketLoss, rng: &mut StdRng) -> bool {
+		match loss {
+			PacketLoss::None => false,
+			PacketLoss::Random(percentage) => chance(rng, *percentage),
+			PacketLoss::Bursts(burst_loss) => {
+				if self.in_bad_state {
+					self.in_bad_state = !chance(rng, burst_loss.bad_to_good_percentage);
+				}
+				else {
+					self.in_bad_state = chance(rng, burst_loss.good_to_bad_percentage);
+				}
+				chance(rng, if self.in_bad_state { burst_loss.bad_loss_percentage } else { burst_loss.good_loss_percentage })
+			},
+		}
+	}
+
+	// every copy of the packet that arrives, with the time it arrives at
+	pub(crate) fn transmit(&mut self, conditions: &NetworkConditions, data: &[u8], now: Instant, rng: &mut StdRng) -> Vec<(Instant, Vec<u8>)> {
+		if self.is_lost(&conditions.loss, rng) {
+			return Vec::new();
+		}
+
+		let copies = if chance(rng, conditions.duplicate_percentage) { 2 } else { 1 };
+		let mut deliveries = Vec::with_capacity(copies);
+		for _ in 0..copies {
+			let mut send_time = now;
+			if let Some(bandwidth) = conditions.bandwidth {
+				let start = self.busy_until.max(now);
+				let queued_bytes = (start - now).as_secs_f64() * bandwidth as f64;
+				if queued_bytes + data.len() as f64 > conditions.max_queued_bytes as f64 {
+					continue;
+				}
+				self.busy_until = start + Duration::from_secs_f64(data.len() as f64 / bandwidth.max(1) as f64);
+				send_time = self.busy_until;
+			}
+
+			let delivery =
+			if chance(rng, conditions.reorder_percentage) {
+				send_time
+			}
+			else {
+				let delivery = (send_time + conditions.delay(rng)).max(self.last_delivery);
+				self.last_delivery = delivery;
+				delivery
+			};
+
+			let mut data = data.to_vec();
+			if !data.is_empty() && chance(rng, conditions.corruption_percentage) {
+				let bit = rng.gen_range(0..data.len() * 8);
+				data[bit / 8] ^= 1 << (bit % 8);
+			}
+			deliveries.push((delivery, data));
+		}
+		deliveries
+	}
+
+	// for events the transport handles reliably, they only get delayed
+	pub(crate) fn delay(&mut self, conditions: &NetworkConditions, now: Instant, rng: &mut StdRng) -> Instant {
+		let delivery = (now + conditions.delay(rng)).max(self.last_delivery);
+		self.last_delivery = delivery;
+		delivery
+	}
+}
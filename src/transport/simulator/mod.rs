@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::transport::{ChannelId, ClientTransport, ClientTransportEvent, DisconnectReason, SendError, ServerConfig, ServerTransport, ServerTransportEvent, TransportMsg};
+use conditions::Link;
+
+mod clock;
+mod conditions;
+mod scheduler;
+
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use conditions::{BurstLoss, Jitter, NetworkConditions, PacketLoss};
+pub use scheduler::SimulatorScheduler;
+
+// the wrapped transport is only locked this long while waiting, since the scheduler needs it for the delayed sends
+const WAIT_SLICE: Duration = Duration::from_millis(1);
+
+pub struct SimulatorConfig {
+	pub scheduler: SimulatorScheduler,
+	// the same seed replays the same loss, jitter, reordering... for the same sends, None picks a random one
+	pub seed: Option<u64>,
+}
+
+impl Default for SimulatorConfig {
+	// shares one scheduler thread with all other simulator transports
+	fn default() -> Self {
+		Self {
+			scheduler: SimulatorScheduler::global(),
+			seed: None,
+		}
+	}
+}
+
+impl SimulatorConfig {
+	fn rng(&self) -> StdRng {
+		match self.seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		}
+	}
+}
+
+// jitter lets later events be due earlier, equal times keep their order
+fn insert_ordered<P>(queue: &mut VecDeque<(Instant, P)>, time: Instant, item: P) {
+	let index = queue.partition_point(|(queued_time, _)| *queued_time <= time);
+	queue.insert(index, (time, item));
+}
+
+// changes the conditions of a simulator that already got moved into a Client
+#[derive(Clone)]
+pub struct ClientSimulatorHandle {
+	conditions: Arc<Mutex<NetworkConditions>>,
+}
+
+impl ClientSimulatorHandle {
+	pub fn conditions(&self) -> NetworkConditions {
+		*self.conditions.lock().unwrap()
+	}
+
+	// applies to every packet sent from now on, packets in flight keep their delay
+	pub fn set_conditions(&self, conditions: NetworkConditions) {
+		*self.conditions.lock().unwrap() = conditions;
+	}
+}
+
+// Simulates the network from the client to the server.
+// NOTE: The conditions apply to whole msgs on top of the wrapped transport, not to its datagrams. Channel guarantees
+// don't hold under the simulation: even reliable channels lose, duplicate, corrupt and reorder msgs, since the
+// transport never sees what got dropped and can't resend it.
+pub struct ClientSimulatorTransport<T: ClientTransport> {
+	transport: Arc<Mutex<T>>,
+	scheduler: SimulatorScheduler,
+	conditions: Arc<Mutex<NetworkConditions>>,
+	link: Link,
+	rng: StdRng,
+}
+
+impl<T: 'static + Send + ClientTransport> ClientSimulatorTransport<T> {
+	pub fn new(conditions: NetworkConditions, transport: T) -> Self {
+		Self::with_config(conditions, transport, SimulatorConfig::default())
+	}
+
+	pub fn with_config(conditions: NetworkConditions, transport: T, config: SimulatorConfig) -> Self {
+		Self {
+			transport: Arc::new(Mutex::new(transport)),
+			link: Link::new(config.scheduler.now()),
+			rng: config.rng(),
+			scheduler: config.scheduler,
+			conditions: Arc::new(Mutex::new(conditions)),
+		}
+	}
+
+	pub fn handle(&self) -> ClientSimulatorHandle {
+		ClientSimulatorHandle {
+			conditions: self.conditions.clone(),
+		}
+	}
+}
+
+impl<T: 'static + Send + ClientTransport> ClientTransport for ClientSimulatorTransport<T> {
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let conditions = *self.conditions.lock().unwrap();
+		for (send_time, data) in self.link.transmit(&conditions, data, self.scheduler.now(), &mut self.rng) {
+			let transport = self.transport.clone();
+			self.scheduler.schedule(send_time, move || {
+				let mut transport = transport.lock().unwrap();
+				// NOTE: Errors of delayed sends can't be reported anymore, just like a real network loses those packets
+				let _ = transport.send(&data, channel_id);
+				// every msg gets delayed on its own, so queueing it any longer would only add to the latency
+				let _ = transport.flush();
+			});
+		}
+		Ok(())
+	}
+
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		self.transport.lock().unwrap().receive_event()
+	}
+
+	// the simulated loss hits every channel
+	fn is_reliable(&self, _channel_id: ChannelId) -> bool {
+		false
+	}
+}
+
+#[derive(Default)]
+struct ServerSimulation {
+	default_conditions: NetworkConditions,
+	client_conditions: HashMap<SocketAddr, NetworkConditions>,
+	simulate_incoming: bool,
+}
+
+impl ServerSimulation {
+	fn conditions(&self, address: &SocketAddr) -> NetworkConditions {
+		*self.client_conditions.get(address).unwrap_or(&self.default_conditions)
+	}
+}
+
+// changes the simulation of a transport that already got moved into a Server
+// NOTE: Changes apply to every packet sent from now on, packets in flight keep their delay
+#[derive(Clone)]
+pub struct ServerSimulatorHandle {
+	simulation: Arc<Mutex<ServerSimulation>>,
+}
+
+impl ServerSimulatorHandle {
+	// used for every client without conditions of its own
+	pub fn set_default_conditions(&self, conditions: NetworkConditions) {
+		self.simulation.lock().unwrap().default_conditions = conditions;
+	}
+
+	pub fn set_client_conditions(&self, address: SocketAddr, conditions: NetworkConditions) {
+		self.simulation.lock().unwrap().client_conditions.insert(address, conditions);
+	}
+
+	// the client falls back to the default conditions
+	pub fn clear_client_conditions(&self, address: SocketAddr) {
+		self.simulation.lock().unwrap().client_conditions.remove(&address);
+	}
+
+	// also applies the conditions of a client to the events coming from it
+	pub fn set_simulate_incoming(&self, simulate_incoming: bool) {
+		self.simulation.lock().unwrap().simulate_incoming = simulate_incoming;
+	}
+}
+
+// Simulates the network between the server and each of its clients.
+// Outgoing msgs are always affected, incoming events only with ServerSimulatorHandle::set_simulate_incoming.
+// NOTE: Just like on the client, the conditions apply to whole msgs, so channel guarantees don't hold under them
+pub struct ServerSimulatorTransport<T: ServerTransport> {
+	transport: Arc<Mutex<T>>,
+	scheduler: SimulatorScheduler,
+	simulation: Arc<Mutex<ServerSimulation>>,
+	outgoing_links: HashMap<SocketAddr, Link>,
+	incoming_links: HashMap<SocketAddr, Link>,
+	// ordered by when the events are due
+	pending_events: VecDeque<(Instant, ServerTransportEvent)>,
+	// the clients of the wrapped transport, since the delayed sends can't report that the client is gone
+	clients: HashSet<SocketAddr>,
+	rng: StdRng,
+}
+
+impl<T: 'static + Send + ServerTransport> ServerSimulatorTransport<T> {
+	// the default conditions apply to every client without conditions of its own
+	pub fn new(default_conditions: NetworkConditions, transport: T) -> Self {
+		Self::with_config(default_conditions, transport, SimulatorConfig::default())
+	}
+
+	pub fn with_config(default_conditions: NetworkConditions, transport: T, config: SimulatorConfig) -> Self {
+		Self {
+			transport: Arc::new(Mutex::new(transport)),
+			rng: config.rng(),
+			scheduler: config.scheduler,
+			simulation: Arc::new(Mutex::new(ServerSimulation {
+				default_conditions,
+				..Default::default()
+			})),
+			outgoing_links: HashMap::new(),
+			incoming_links: HashMap::new(),
+			pending_events: VecDeque::new(),
+			clients: HashSet::new(),
+		}
+	}
+
+	pub fn handle(&self) -> ServerSimulatorHandle {
+		ServerSimulatorHandle {
+			simulation: self.simulation.clone(),
+		}
+	}
+
+	fn delay_event(&mut self, event: ServerTransportEvent, now: Instant) {
+		let simulation = self.simulation.lock().unwrap();
+		let address = match &event {
+			ServerTransportEvent::NewClient(address) | ServerTransportEvent::ClientDisconnected(address, _) | ServerTransportEvent::ClientRejected(address, _) => *address,
+			ServerTransportEvent::NewMsg(msg) => msg.sender_address,
+			// events that were already delayed still come first after turning the simulation off
+			ServerTransportEvent::FailedToReceiveMsg(_) | ServerTransportEvent::FailedToAcceptConnection(_) => {
+				insert_ordered(&mut self.pending_events, now, event);
+				return;
+			},
+		};
+		let conditions = if simulation.simulate_incoming { simulation.conditions(&address) } else { NetworkConditions::default() };
+		let link = self.incoming_links.entry(address).or_insert_with(|| Link::new(now));
+
+		match event {
+			// only msgs are affected by loss, connection events are handled reliably by the transport
+			ServerTransportEvent::NewMsg(msg) => {
+				for (receive_time, data) in link.transmit(&conditions, &msg.data, now, &mut self.rng) {
+					let event = ServerTransportEvent::NewMsg(TransportMsg {
+						sender_address: address,
+						channel_id: msg.channel_id,
+						data,
+					});
+					insert_ordered(&mut self.pending_events, receive_time, event);
+				}
+			},
+			event => {
+				let receive_time = link.delay(&conditions, now, &mut self.rng);
+				match &event {
+					ServerTransportEvent::NewClient(_) => {
+						self.clients.insert(address);
+					},
+					ServerTransportEvent::ClientDisconnected(..) | ServerTransportEvent::ClientRejected(..) => {
+						self.clients.remove(&address);
+						self.incoming_links.remove(&address);
+						self.outgoing_links.remove(&address);
+					},
+					_ => {},
+				}
+				insert_ordered(&mut self.pending_events, receive_time, event);
+			},
+		}
+	}
+}
+
+impl<T: 'static + Send + ServerTransport> ServerTransport for ServerSimulatorTransport<T> {
+	fn receive_event(&mut self) -> Option<ServerTransportEvent> {
+		let now = self.scheduler.now();
+		loop {
+			let event = self.transport.lock().unwrap().receive_event();
+			let Some(event) = event else { break };
+			self.delay_event(event, now);
+		}
+
+		if self.pending_events.front().is_some_and(|(receive_time, _)| *receive_time <= now) {
+			self.pending_events.pop_front().map(|(_, event)| event)
+		}
+		else {
+			None
+		}
+	}
+
+	// wakes up as soon as the wrapped transport has an event or the next delayed one is due
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			if let Some(event) = self.receive_event() {
+				return Some(event);
+			}
+			let now = Instant::now();
+			if deadline.is_some_and(|deadline| deadline <= now) {
+				return None;
+			}
+
+			let mut wait = WAIT_SLICE;
+			if let Some((receive_time, _)) = self.pending_events.front() {
+				wait = wait.min(receive_time.saturating_duration_since(self.scheduler.now()));
+			}
+			if let Some(deadline) = deadline {
+				wait = wait.min(deadline - now);
+			}
+			let event = self.transport.lock().unwrap().wait_event(Some(wait));
+			if let Some(event) = event {
+				let now = self.scheduler.now();
+				self.delay_event(event, now);
+			}
+		}
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		if !self.clients.contains(&address) {
+			return Err(SendError::Disconnected);
+		}
+		let now = self.scheduler.now();
+		let conditions = self.simulation.lock().unwrap().conditions(&address);
+		let link = self.outgoing_links.entry(address).or_insert_with(|| Link::new(now));
+		for (send_time, data) in link.transmit(&conditions, data, now, &mut self.rng) {
+			let transport = self.transport.clone();
+			self.scheduler.schedule(send_time, move || {
+				let mut transport = transport.lock().unwrap();
+				// NOTE: Errors of delayed sends can't be reported anymore, just like a real network loses those packets
+				let _ = transport.send(address, &data, channel_id);
+				let _ = transport.flush();
+			});
+		}
+		Ok(())
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		let now = self.scheduler.now();
+		let conditions = self.simulation.lock().unwrap().conditions(&address);
+		self.clients.remove(&address);
+		let mut link = self.outgoing_links.remove(&address).unwrap_or_else(|| Link::new(now));
+		// travels through the same delay, so it can't overtake the msgs sent before it
+		let send_time = link.delay(&conditions, now, &mut self.rng);
+		let transport = self.transport.clone();
+		self.scheduler.schedule(send_time, move || transport.lock().unwrap().disconnect(address, reason));
+	}
+
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.transport.lock().unwrap().apply_config(config);
+	}
+}
@@ -0,0 +1,163 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::Instant;
+use crate::transport::simulator::clock::{Clock, SystemClock};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct ScheduledJob {
+	due: Instant,
+	// keeps jobs that are due at the same time in the order they were scheduled
+	seq: u64,
+	job: Job,
+}
+
+impl PartialEq for ScheduledJob {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for ScheduledJob {}
+
+impl PartialOrd for ScheduledJob {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for ScheduledJob {
+	// reversed, the binary heap has to pop the earliest job first
+	fn cmp(&self, other: &Self) -> Ordering {
+		(other.due, other.seq).cmp(&(self.due, self.seq))
+	}
+}
+
+struct SchedulerState {
+	jobs: BinaryHeap<ScheduledJob>,
+	next_seq: u64,
+	// the thread ends once no handle is left and every job ran
+	handles: usize,
+}
+
+struct SchedulerShared {
+	state: Mutex<SchedulerState>,
+	wake_up: Condvar,
+	clock: Box<dyn Clock>,
+}
+
+// Runs the delayed sends of any number of simulator transports.
+// Either on a thread that sleeps until the next send is due, or manually on an injected clock.
+pub struct SimulatorScheduler {
+	shared: Arc<SchedulerShared>,
+}
+
+impl Default for SimulatorScheduler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl SimulatorScheduler {
+	pub fn new() -> Self {
+		let scheduler = Self::create(Box::new(SystemClock));
+		let shared = scheduler.shared.clone();
+		std::thread::Builder::new()
+			.name("Networking Simulator Thread".to_string())
+			.spawn(move || Self::scheduler_thread(shared))
+			.unwrap();
+		scheduler
+	}
+
+	// No thread, the due jobs run inside run_due_jobs.
+	// Together with a ManualClock and a seed this makes the simulation deterministic in tests.
+	pub fn with_clock(clock: impl Clock + 'static) -> Self {
+		Self::create(Box::new(clock))
+	}
+
+	// the scheduler simulator transports use unless they get their own
+	pub(crate) fn global() -> Self {
+		static GLOBAL: OnceLock<SimulatorScheduler> = OnceLock::new();
+		GLOBAL.get_or_init(Self::new).clone()
+	}
+
+	fn create(clock: Box<dyn Clock>) -> Self {
+		Self {
+			shared: Arc::new(SchedulerShared {
+				state: Mutex::new(SchedulerState {
+					jobs: BinaryHeap::new(),
+					next_seq: 0,
+					handles: 1,
+				}),
+				wake_up: Condvar::new(),
+				clock,
+			}),
+		}
+	}
+
+	pub fn now(&self) -> Instant {
+		self.shared.clock.now()
+	}
+
+	// runs every job that is due by now on the clock, in order
+	pub fn run_due_jobs(&self) {
+		let now = self.now();
+		let mut state = self.shared.state.lock().unwrap();
+		while state.jobs.peek().is_some_and(|job| job.due <= now) {
+			let job = state.jobs.pop().unwrap();
+			drop(state);
+			(job.job)();
+			state = self.shared.state.lock().unwrap();
+		}
+	}
+
+	pub(crate) fn schedule(&self, due: Instant, job: impl FnOnce() + Send + 'static) {
+		let mut state = self.shared.state.lock().unwrap();
+		let seq = state.next_seq;
+		state.next_seq += 1;
+		state.jobs.push(ScheduledJob {
+			due,
+			seq,
+			job: Box::new(job),
+		});
+		self.shared.wake_up.notify_one();
+	}
+
+	fn scheduler_thread(shared: Arc<SchedulerShared>) {
+		let mut state = shared.state.lock().unwrap();
+		loop {
+			let now = shared.clock.now();
+			match state.jobs.peek() {
+				Some(job) if job.due <= now => {
+					let job = state.jobs.pop().unwrap();
+					drop(state);
+					(job.job)();
+					state = shared.state.lock().unwrap();
+				},
+				Some(job) => {
+					let timeout = job.due - now;
+					state = shared.wake_up.wait_timeout(state, timeout).unwrap().0;
+				},
+				None if state.handles == 0 => return,
+				None => state = shared.wake_up.wait(state).unwrap(),
+			}
+		}
+	}
+}
+
+impl Clone for SimulatorScheduler {
+	fn clone(&self) -> Self {
+		self.shared.state.lock().unwrap().handles += 1;
+		Self {
+			shared: self.shared.clone(),
+		}
+	}
+}
+
+impl Drop for SimulatorScheduler {
+	fn drop(&mut self) {
+		self.shared.state.lock().unwrap().handles -= 1;
+		self.shared.wake_up.notify_one();
+	}
+}
@@ -1,36 +1,232 @@
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::io::{self, Read, Write};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use crate::transport::{MAX_MSG_SIZE, ClientTransport, ServerTransport, TransportMsg, ServerTransportEvent};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::transport::{DEFAULT_MAX_MSG_SIZE, MAX_MSG_SIZE, ChannelId, ClientTransport, Coalescing, ConnectionRejectedReason, DisconnectReason, SendError, ServerConfig, ServerTransport, TransportMsg, ServerTransportEvent, check_channel, wait_on_channel};
 
 use super::ClientTransportEvent;
 
-pub struct TcpClientTransport {
+#[derive(Serialize, Deserialize)]
+pub(crate) enum TcpMsg {
+	Msg(ChannelId, Vec<u8>),
+	Disconnected(DisconnectReason),
+}
+
+// NOTE: Tcp is a byte stream without msg boundaries, so every msg is prefixed with its length
+const FRAME_HEADER_SIZE: usize = std::mem::size_of::<u32>();
+// upper bound of the bincode encoding of TcpMsg::Msg without its data
+const TCP_MSG_HEADER_SIZE: usize = 4 + 1 + 8;
+// the length prefix limits how large a single frame can get
+const MAX_TCP_MSG_SIZE: usize = u32::MAX as usize - TCP_MSG_HEADER_SIZE;
+
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+	// larger msgs can't be sent and receiving them breaks the connection
+	// NOTE: Client and server should use the same limit, everything above u32::MAX bytes is rejected anyways
+	pub max_msg_size: usize,
+	// queued msgs of a stream get written with a single write
+	pub coalescing: Coalescing,
+	// msgs can only be sent on channels below this, every one of them is reliable and ordered
+	pub channel_count: usize,
+}
+
+impl Default for TcpConfig {
+	fn default() -> Self {
+		Self {
+			max_msg_size: DEFAULT_MAX_MSG_SIZE,
+			coalescing: Coalescing::Disabled,
+			channel_count: 1,
+		}
+	}
+}
+
+impl TcpConfig {
+	fn max_msg_size(&self) -> usize {
+		self.max_msg_size.min(MAX_TCP_MSG_SIZE)
+	}
+}
+
+pub(crate) fn frame_tcp_msg(tcp_msg: &TcpMsg) -> Vec<u8> {
+	let data = bincode::serialize(tcp_msg).unwrap();
+	let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + data.len());
+	frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	frame.extend_from_slice(&data);
+	frame
+}
+
+pub(crate) fn frame_msg(data: &[u8], channel_id: ChannelId, config: &TcpConfig) -> Result<Vec<u8>, SendError> {
+	check_channel(channel_id, config.channel_count)?;
+	let max_size = config.max_msg_size();
+	if data.len() > max_size {
+		return Err(SendError::MsgTooLarge {
+			size: data.len(),
+			max_size,
+		});
+	}
+	Ok(frame_tcp_msg(&TcpMsg::Msg(channel_id, data.to_vec())))
+}
+
+// tells the client why before closing the stream, without spawning anything for it
+pub(crate) fn rejection_frame(reason: ConnectionRejectedReason) -> Vec<u8> {
+	frame_tcp_msg(&TcpMsg::Disconnected(DisconnectReason::Rejected(reason)))
+}
+
+pub(crate) struct FrameReader {
+	buffer: Vec<u8>,
+	// where the next frame starts, the frames before it only get removed once per read
+	start: usize,
+	max_frame_size: usize,
+}
+
+impl FrameReader {
+	pub(crate) fn new(config: &TcpConfig) -> Self {
+		Self {
+			buffer: Vec::new(),
+			start: 0,
+			max_frame_size: config.max_msg_size() + TCP_MSG_HEADER_SIZE,
+		}
+	}
+
+	pub(crate) fn push(&mut self, bytes: &[u8]) {
+		self.buffer.drain(..self.start);
+		self.start = 0;
+		self.buffer.extend_from_slice(bytes);
+	}
+
+	pub(crate) fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let buffer = &self.buffer[self.start..];
+		if buffer.len() < FRAME_HEADER_SIZE {
+			return Ok(None);
+		}
+
+		let mut header = [0; FRAME_HEADER_SIZE];
+		header.copy_from_slice(&buffer[..FRAME_HEADER_SIZE]);
+		let msg_size = u32::from_le_bytes(header) as usize;
+		if msg_size > self.max_frame_size {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("received msg of {msg_size} bytes, which is over {} bytes", self.max_frame_size)));
+		}
+
+		if buffer.len() < FRAME_HEADER_SIZE + msg_size {
+			return Ok(None);
+		}
+
+		let frame = buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + msg_size].to_vec();
+		self.start += FRAME_HEADER_SIZE + msg_size;
+		Ok(Some(frame))
+	}
+}
+
+// the frames of a stream wait in its send queue until the next flush, if coalescing is enabled
+struct QueuedStream {
 	stream: TcpStream,
+	send_queue: Vec<u8>,
+}
+
+impl QueuedStream {
+	fn new(stream: TcpStream) -> Self {
+		Self {
+			stream,
+			send_queue: Vec::new(),
+		}
+	}
+
+	fn send(&mut self, frame: &[u8], coalescing: Coalescing) -> io::Result<()> {
+		match coalescing {
+			Coalescing::Disabled => self.stream.write_all(frame),
+			Coalescing::Manual | Coalescing::Interval(_) => {
+				self.send_queue.extend_from_slice(frame);
+				Ok(())
+			},
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		if self.send_queue.is_empty() {
+			return Ok(());
+		}
+		let result = self.stream.write_all(&self.send_queue);
+		self.send_queue.clear();
+		result
+	}
+}
+
+// Flushes the send queues once per interval, so that no msg waits longer than that.
+// NOTE: Write errors are ignored, since the listen threads notice broken streams as well
+fn spawn_flush_thread<T: 'static + Send>(name: &str, streams: &Arc<Mutex<T>>, coalescing: Coalescing, flush: fn(&mut T)) {
+	let Coalescing::Interval(interval) = coalescing else {
+		return;
+	};
+
+	let streams = Arc::downgrade(streams);
+	std::thread::Builder::new()
+		.name(name.to_string())
+		.spawn(move || {
+			// stops once the transport got dropped
+			loop {
+				std::thread::sleep(interval);
+				let Some(streams) = streams.upgrade() else {
+					return;
+				};
+				flush(&mut streams.lock().unwrap());
+			}
+		})
+		.unwrap();
+}
+
+pub struct TcpClientTransport {
+	stream: Arc<Mutex<QueuedStream>>,
 	transport_msg_receiver: Receiver<ClientTransportEvent>,
 	server_disconnected: bool,
+	config: TcpConfig,
 }
 
 impl TcpClientTransport {
 	pub fn new<A: ToSocketAddrs>(server_address: A) -> io::Result<Self> {
+		Self::with_config(server_address, TcpConfig::default())
+	}
+
+	pub fn with_config<A: ToSocketAddrs>(server_address: A, config: TcpConfig) -> io::Result<Self> {
 		let stream = TcpStream::connect(server_address)?;
 		let stream_clone = stream.try_clone().unwrap();
+		let frame_reader = FrameReader::new(&config);
 		let (sender, receiver) = std::sync::mpsc::channel();
 		std::thread::Builder::new()
 			.name("Tcp Client Listen Thread".to_string())
-			.spawn(move || Self::listen_thread(stream_clone, sender))
+			.spawn(move || Self::listen_thread(stream_clone, frame_reader, sender))
 			.unwrap();
+		let stream = Arc::new(Mutex::new(QueuedStream::new(stream)));
+		spawn_flush_thread("Tcp Client Flush Thread", &stream, config.coalescing, |stream| {
+			let _ = stream.flush();
+		});
 
 		Ok(Self {
 			stream,
 			transport_msg_receiver: receiver,
 			server_disconnected: false,
+			config,
 		})
 	}
 
-	fn listen_thread(mut stream: TcpStream, sender: Sender<ClientTransportEvent>) {
+	// NOTE: A failed write doesn't emit an event itself, the listen thread notices the broken stream as well
+	fn track_disconnect(&mut self, event: Option<ClientTransportEvent>) -> Option<ClientTransportEvent> {
+		if let Some(ClientTransportEvent::ServerDisconnected(_)) = &event {
+			self.server_disconnected = true;
+		}
+		event
+	}
+
+	fn track_write_error(&mut self, result: io::Result<()>) -> Result<(), SendError> {
+		result.map_err(|e| {
+			self.server_disconnected = true;
+			SendError::Io(e)
+		})
+	}
+
+	fn listen_thread(mut stream: TcpStream, mut frame_reader: FrameReader, sender: Sender<ClientTransportEvent>) {
 		let mut buffer = [0; MAX_MSG_SIZE];
 
 		loop {
@@ -39,21 +235,41 @@ impl TcpClientTransport {
 					let client_disconnected = bytes_read == 0;
 
 					if client_disconnected {
-						let _ = sender.send(ClientTransportEvent::ServerDisconnected);
+						let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::Closed));
 						return;
 					}
-					else {
-						if sender.send(
-							ClientTransportEvent::NewMsg(buffer[..bytes_read].to_vec())
-						).is_err() {
-							return;
+
+					frame_reader.push(&buffer[..bytes_read]);
+					loop {
+						match frame_reader.next_frame() {
+							Ok(Some(frame)) => {
+								let event = match bincode::deserialize(&frame) {
+									Ok(TcpMsg::Msg(channel_id, data)) => ClientTransportEvent::NewMsg(channel_id, data),
+									Ok(TcpMsg::Disconnected(reason)) => {
+										let _ = sender.send(ClientTransportEvent::ServerDisconnected(reason));
+										return;
+									},
+									Err(e) => ClientTransportEvent::FailedToReceiveMsg(io::Error::other(e)),
+								};
+								if sender.send(event).is_err() {
+									return;
+								}
+							},
+							Ok(None) => break,
+							Err(e) => {
+								// the stream can't be resynchronized after a corrupted frame header
+								let _ = sender.send(ClientTransportEvent::FailedToReceiveMsg(e));
+								let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::ConnectionLost));
+								let _ = stream.shutdown(Shutdown::Both);
+								return;
+							},
 						}
-					};
+					}
 				},
 				Err(e) => {
 					match e.kind() {
 						io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
-							let _ = sender.send(ClientTransportEvent::ServerDisconnected);
+							let _ = sender.send(ClientTransportEvent::ServerDisconnected(DisconnectReason::ConnectionLost));
 							return;
 						}
 						_ => {
@@ -70,26 +286,44 @@ impl TcpClientTransport {
 
 impl ClientTransport for TcpClientTransport {
 	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		let event = self.transport_msg_receiver.try_recv().ok();
+		self.track_disconnect(event)
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		let event = wait_on_channel(&self.transport_msg_receiver, timeout);
+		self.track_disconnect(event)
+	}
+
+	// tcp is always reliable and ordered, so channels only tag the msgs
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
 		if self.server_disconnected {
-			Some(ClientTransportEvent::ServerDisconnected)
-		}
-		else {
-			self.transport_msg_receiver.try_recv().ok()
+			return Err(SendError::Disconnected);
 		}
-	}
 
-	fn send(&mut self, data: &[u8]) {
-		assert!(data.len() < MAX_MSG_SIZE, "Sending packets over {MAX_MSG_SIZE} bytes is not supported: see MAX_MSG_SIZE!");
+		let frame = frame_msg(data, channel_id, &self.config)?;
+		let result = self.stream.lock().unwrap().send(&frame, self.config.coalescing);
+		self.track_write_error(result)
+	}
 
-		if self.stream.write_all(data).is_err() {
-			self.server_disconnected = true;
+	fn flush(&mut self) -> Result<(), SendError> {
+		if self.server_disconnected {
+			return Err(SendError::Disconnected);
 		}
+
+		let result = self.stream.lock().unwrap().flush();
+		self.track_write_error(result)
 	}
 }
 
+type ClientStreams = Arc<Mutex<HashMap<SocketAddr, QueuedStream>>>;
+
 pub struct TcpServerTransport {
 	transport_msg_receiver: Receiver<ServerTransportEvent>,
-	client_streams: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+	client_streams: ClientStreams,
+	// usize::MAX without a limit, connections over it are rejected before getting a thread
+	max_clients: Arc<AtomicUsize>,
+	config: TcpConfig,
 }
 
 impl TcpServerTransport {
@@ -98,58 +332,105 @@ impl TcpServerTransport {
 	}
 
 	pub fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, TcpConfig::default())
+	}
+
+	pub fn with_config<A: ToSocketAddrs>(address: A, config: TcpConfig) -> io::Result<Self> {
 		let listener = TcpListener::bind(address)?;
 		let (send_channel, receive_channel) = std::sync::mpsc::channel();
-		let client_streams = Arc::new(Mutex::new(HashMap::new()));
+		let client_streams = ClientStreams::default();
 		let client_streams_clone = client_streams.clone();
+		let max_clients = Arc::new(AtomicUsize::new(usize::MAX));
+		let max_clients_clone = max_clients.clone();
+		let config_clone = config.clone();
 		std::thread::Builder::new()
 			.name("Tcp Listen Thread".to_string())
-			.spawn(move || Self::listen_thread(send_channel, listener, client_streams_clone))
+			.spawn(move || Self::listen_thread(send_channel, listener, client_streams_clone, max_clients_clone, config_clone))
 			.unwrap();
+		spawn_flush_thread("Tcp Flush Thread", &client_streams, config.coalescing, |client_streams| {
+			for stream in client_streams.values_mut() {
+				let _ = stream.flush();
+			}
+		});
 
 		Ok(Self {
 			transport_msg_receiver: receive_channel,
 			client_streams,
+			max_clients,
+			config,
 		})
 	}
 
-	fn handle_client_thread(mut stream: TcpStream, address: SocketAddr, sender: Arc<Sender<ServerTransportEvent>>) {
-		let mut buffer = [0; MAX_MSG_SIZE];
+	fn handle_client_thread(stream: TcpStream, address: SocketAddr, frame_reader: FrameReader, sender: Arc<Sender<ServerTransportEvent>>, client_streams: ClientStreams) {
 		if sender.send(ServerTransportEvent::NewClient(address)).is_err() {
 			return;
 		}
 
+		let reason = Self::receive_client_msgs(stream, address, frame_reader, &sender);
+		// kicked clients were already removed and shouldn't be reported as disconnected
+		let was_connected = client_streams.lock().unwrap().remove(&address).is_some();
+		if let Some(reason) = reason {
+			if was_connected {
+				let _ = sender.send(ServerTransportEvent::ClientDisconnected(address, reason));
+			}
+		}
+	}
+
+	// returns the reason once the client disconnected, or None if the transport got dropped
+	fn receive_client_msgs(mut stream: TcpStream, address: SocketAddr, mut frame_reader: FrameReader, sender: &Sender<ServerTransportEvent>) -> Option<DisconnectReason> {
+		let mut buffer = [0; MAX_MSG_SIZE];
+
 		loop {
 			match stream.read(&mut buffer) {
 				Ok(bytes_read) => {
 					let client_disconnected = bytes_read == 0;
 
 					if client_disconnected {
-						let _ = sender.send(ServerTransportEvent::ClientDisconnected(address));
-						return;
+						return Some(DisconnectReason::Closed);
 					}
-					else {
-						if sender.send(
-							ServerTransportEvent::NewMsg(
-								TransportMsg {
-									sender_address: address,
-									data: buffer[..bytes_read].to_vec()
+
+					frame_reader.push(&buffer[..bytes_read]);
+					loop {
+						match frame_reader.next_frame() {
+							Ok(Some(frame)) => {
+								let event = match bincode::deserialize(&frame) {
+									Ok(TcpMsg::Msg(channel_id, data)) => {
+										ServerTransportEvent::NewMsg(
+											TransportMsg {
+												sender_address: address,
+												channel_id,
+												data,
+											}
+										)
+									},
+									Ok(TcpMsg::Disconnected(reason)) => {
+										let _ = stream.shutdown(Shutdown::Both);
+										return Some(reason);
+									},
+									Err(e) => ServerTransportEvent::FailedToReceiveMsg(io::Error::other(e)),
+								};
+								if sender.send(event).is_err() {
+									return None;
 								}
-							)
-						).is_err() {
-							return;
+							},
+							Ok(None) => break,
+							Err(e) => {
+								// the stream can't be resynchronized after a corrupted frame header
+								let _ = sender.send(ServerTransportEvent::FailedToReceiveMsg(e));
+								let _ = stream.shutdown(Shutdown::Both);
+								return Some(DisconnectReason::ConnectionLost);
+							},
 						}
-					};
+					}
 				},
 				Err(e) => {
 					match e.kind() {
 						io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
-							let _ = sender.send(ServerTransportEvent::ClientDisconnected(address));
-							return;
+							return Some(DisconnectReason::ConnectionLost);
 						}
 						_ => {
 							if sender.send(ServerTransportEvent::FailedToReceiveMsg(e)).is_err() {
-								return;
+								return None;
 							}
 						},
 					}
@@ -158,19 +439,30 @@ impl TcpServerTransport {
 		}
 	}
 
-	fn listen_thread(sender: Sender<ServerTransportEvent>, listener: TcpListener, client_streams: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>) {
+	fn listen_thread(sender: Sender<ServerTransportEvent>, listener: TcpListener, client_streams: ClientStreams, max_clients: Arc<AtomicUsize>, config: TcpConfig) {
 		let sender = Arc::new(sender);
 
 		for stream in listener.incoming() {
 			match stream {
-				Ok(stream) => {
-					let sender_clone = sender.clone();
+				Ok(mut stream) => {
 					let address = stream.peer_addr().unwrap();
+					if client_streams.lock().unwrap().len() >= max_clients.load(Ordering::Relaxed) {
+						let _ = stream.write_all(&rejection_frame(ConnectionRejectedReason::ServerFull));
+						let _ = stream.shutdown(Shutdown::Both);
+						if sender.send(ServerTransportEvent::ClientRejected(address, ConnectionRejectedReason::ServerFull)).is_err() {
+							return;
+						}
+						continue;
+					}
+
+					let sender_clone = sender.clone();
+					let client_streams_clone = client_streams.clone();
 					let stream_clone = stream.try_clone().unwrap();
-					client_streams.lock().unwrap().insert(address, stream);
+					let frame_reader = FrameReader::new(&config);
+					client_streams.lock().unwrap().insert(address, QueuedStream::new(stream));
 					std::thread::Builder::new()
 						.name("Client Tcp Thread".to_string())
-						.spawn(move || Self::handle_client_thread(stream_clone, address, sender_clone))
+						.spawn(move || Self::handle_client_thread(stream_clone, address, frame_reader, sender_clone, client_streams_clone))
 						.unwrap();
 				},
 				Err(e) => {
@@ -188,11 +480,56 @@ impl ServerTransport for TcpServerTransport {
 		self.transport_msg_receiver.try_recv().ok()
 	}
 
-	fn send(&mut self, address: std::net::SocketAddr, data: &[u8]) {
-		if let Ok(client_streams) = &mut self.client_streams.lock() {
-			if let Some(stream) = client_streams.get_mut(&address) {
-				let _ =stream.write_all(data);
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		wait_on_channel(&self.transport_msg_receiver, timeout)
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let frame = frame_msg(data, channel_id, &self.config)?;
+		let mut client_streams = self.client_streams.lock().unwrap();
+		let stream = client_streams.get_mut(&address).ok_or(SendError::Disconnected)?;
+		stream.send(&frame, self.config.coalescing)?;
+		Ok(())
+	}
+
+	fn send_to_many(&mut self, addresses: &[SocketAddr], data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let frame = frame_msg(data, channel_id, &self.config)?;
+		let mut client_streams = self.client_streams.lock().unwrap();
+		let mut result = Ok(());
+		for address in addresses {
+			if let Some(stream) = client_streams.get_mut(address) {
+				if let Err(e) = stream.send(&frame, self.config.coalescing) {
+					if result.is_ok() {
+						result = Err(SendError::Io(e));
+					}
+				}
+			}
+		}
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		let mut result = Ok(());
+		for stream in self.client_streams.lock().unwrap().values_mut() {
+			if let Err(e) = stream.flush() {
+				if result.is_ok() {
+					result = Err(SendError::Io(e));
+				}
 			}
 		}
+		result
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		if let Some(mut stream) = self.client_streams.lock().unwrap().remove(&address) {
+			// queued msgs still go out before the kick
+			let _ = stream.flush();
+			let _ = stream.stream.write_all(&frame_tcp_msg(&TcpMsg::Disconnected(reason)));
+			let _ = stream.stream.shutdown(Shutdown::Both);
+		}
+	}
+
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.max_clients.store(config.max_clients.unwrap_or(usize::MAX), Ordering::Relaxed);
 	}
 }
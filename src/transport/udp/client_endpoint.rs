@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::Instant;
+
+use crate::transport::{ChannelId, ClientTransportEvent, DeliveryMode, DisconnectReason, SendError, check_channel};
+use super::connection::{Connection, Packet};
+use super::{HANDSHAKE_RESEND_INTERVAL, MTU_PROBE_SIZES, MTU_PROBE_TIMEOUT, TRANSPORT_VERSION, UdpConfig, UdpMsg, check_msg_size, mtu_probe_padding, serialize_udp_msg};
+
+enum State {
+	Connecting,
+	Challenged(u64),
+	Connected(Box<Connection>),
+	Disconnected,
+}
+
+// Client side of the udp protocol, independent of how the socket is driven
+pub(crate) struct ClientEndpoint {
+	config: UdpConfig,
+	state: State,
+	// msgs sent before the server accepted the connection
+	queued_msgs: Vec<(ChannelId, Vec<u8>)>,
+	next_handshake_send: Instant,
+	last_handshake_received: Instant,
+	// the ChallengeResponse is held back until the probes got answered or this passed
+	mtu_probe_deadline: Option<Instant>,
+	// the largest datagram the server answered, starts at our own configured mtu
+	probed_mtu: usize,
+	events: VecDeque<ClientTransportEvent>,
+	outgoing: Vec<Vec<u8>>,
+}
+
+impl ClientEndpoint {
+	pub(crate) fn new(config: UdpConfig, now: Instant) -> Self {
+		let mut endpoint = Self {
+			state: State::Connecting,
+			queued_msgs: Vec::new(),
+			next_handshake_send: now,
+			last_handshake_received: now,
+			mtu_probe_deadline: None,
+			probed_mtu: config.mtu,
+			events: VecDeque::new(),
+			outgoing: Vec::new(),
+			config,
+		};
+		endpoint.update(now);
+		endpoint
+	}
+
+	pub(crate) fn send(&mut self, data: &[u8], channel_id: ChannelId, now: Instant) -> Result<(), SendError> {
+		check_channel(channel_id, self.config.channels.len())?;
+		check_msg_size(data, &self.config)?;
+
+		match &mut self.state {
+			State::Connecting | State::Challenged(_) => self.queued_msgs.push((channel_id, data.to_vec())),
+			State::Connected(connection) => {
+				for packet in connection.send(data, channel_id, now) {
+					self.outgoing.push(serialize_udp_msg(&UdpMsg::Packet(packet)));
+				}
+			},
+			State::Disconnected => return Err(SendError::Disconnected),
+		}
+		Ok(())
+	}
+
+	pub(crate) fn is_reliable(&self, channel_id: ChannelId) -> bool {
+		matches!(self.config.channels.get(channel_id as usize), Some(DeliveryMode::ReliableUnordered | DeliveryMode::ReliableOrdered))
+	}
+
+	pub(crate) fn flush(&mut self, now: Instant) {
+		if let State::Connected(connection) = &mut self.state {
+			for packet in connection.flush(now) {
+				self.outgoing.push(serialize_udp_msg(&UdpMsg::Packet(packet)));
+			}
+		}
+	}
+
+	pub(crate) fn receive(&mut self, datagram: &[u8], now: Instant) {
+		let udp_msg = match bincode::deserialize(datagram) {
+			Ok(udp_msg) => udp_msg,
+			Err(e) => {
+				self.events.push_back(ClientTransportEvent::FailedToReceiveMsg(io::Error::other(e)));
+				return;
+			},
+		};
+
+		match (udp_msg, &mut self.state) {
+			(_, State::Disconnected) => {},
+			(UdpMsg::Challenge { token }, State::Connecting) => {
+				self.state = State::Challenged(token);
+				self.last_handshake_received = now;
+				self.next_handshake_send = now;
+				if self.config.mtu_probing && self.unanswered_mtu_probes().next().is_some() {
+					self.mtu_probe_deadline = Some(now + MTU_PROBE_TIMEOUT);
+				}
+				self.update(now);
+			},
+			(UdpMsg::MtuProbeAck { size, .. }, State::Challenged(_)) => {
+				self.probed_mtu = self.probed_mtu.max(size as usize);
+				if self.unanswered_mtu_probes().next().is_none() {
+					// no need to wait for the timeout, since there is nothing left to probe
+					self.mtu_probe_deadline = None;
+					self.next_handshake_send = now;
+					self.update(now);
+				}
+			},
+			(UdpMsg::ConnectionAccepted { mtu }, State::Challenged(_)) => self.accept_connection(mtu as usize, now),
+			(UdpMsg::ConnectionRejected(reason), State::Connecting | State::Challenged(_)) => {
+				self.state = State::Disconnected;
+				self.events.push_back(ClientTransportEvent::ConnectionRejected(reason));
+			},
+			(UdpMsg::Packet(packet), State::Connected(_)) => self.receive_packet(packet, now),
+			(UdpMsg::Disconnected(reason), _) => self.server_disconnected(reason),
+			_ => {},
+		}
+	}
+
+	pub(crate) fn update(&mut self, now: Instant) {
+		match &mut self.state {
+			State::Connecting | State::Challenged(_) => {
+				if now.duration_since(self.last_handshake_received) >= self.config.idle_timeout {
+					self.server_disconnected(DisconnectReason::TimedOut);
+				}
+				else if self.next_handshake_send <= now {
+					self.send_handshake_msgs(now);
+					self.next_handshake_send = now + HANDSHAKE_RESEND_INTERVAL;
+				}
+			},
+			State::Connected(connection) => {
+				if let Some(reason) = connection.failure(now) {
+					// in case the server is still alive, but only its packets got lost
+					self.outgoing.push(serialize_udp_msg(&UdpMsg::Disconnected(reason.clone())));
+					self.server_disconnected(reason);
+					return;
+				}
+
+				for packet in connection.update(now) {
+					self.outgoing.push(serialize_udp_msg(&UdpMsg::Packet(packet)));
+				}
+			},
+			State::Disconnected => {},
+		}
+	}
+
+	pub(crate) fn disconnect(&mut self) {
+		// queued msgs still go out before the connection closes
+		self.flush(Instant::now());
+		if !matches!(self.state, State::Disconnected) {
+			self.state = State::Disconnected;
+			self.outgoing.push(serialize_udp_msg(&UdpMsg::Disconnected(DisconnectReason::Closed)));
+		}
+	}
+
+	pub(crate) fn server_disconnected(&mut self, reason: DisconnectReason) {
+		if !matches!(self.state, State::Disconnected) {
+			self.state = State::Disconnected;
+			self.events.push_back(ClientTransportEvent::ServerDisconnected(reason));
+		}
+	}
+
+	pub(crate) fn is_disconnected(&self) -> bool {
+		matches!(self.state, State::Disconnected)
+	}
+
+	pub(crate) fn poll_event(&mut self) -> Option<ClientTransportEvent> {
+		self.events.pop_front()
+	}
+
+	pub(crate) fn drain_outgoing(&mut self) -> impl Iterator<Item = Vec<u8>> + '_ {
+		self.outgoing.drain(..)
+	}
+
+	// None until the server accepted the connection
+	pub(crate) fn mtu(&self) -> Option<usize> {
+		match &self.state {
+			State::Connected(connection) => Some(connection.mtu()),
+			_ => None,
+		}
+	}
+
+	fn send_handshake_msgs(&mut self, now: Instant) {
+		let State::Challenged(token) = self.state else {
+			self.outgoing.push(serialize_udp_msg(&UdpMsg::ConnectionRequest {
+				transport_version: TRANSPORT_VERSION,
+				application_id: self.config.application_id,
+				protocol_version: self.config.protocol_version,
+			}));
+			return;
+		};
+
+		let probing = self.mtu_probe_deadline.is_some_and(|deadline| now < deadline);
+		if !probing {
+			self.mtu_probe_deadline = None;
+			self.outgoing.push(serialize_udp_msg(&UdpMsg::ChallengeResponse {
+				token,
+				mtu: self.probed_mtu as u32,
+			}));
+			return;
+		}
+
+		// probes get resent along with the handshake, so that a single lost one doesn't lower the mtu
+		let probes: Vec<Vec<u8>> = self.unanswered_mtu_probes()
+			.map(|size| serialize_udp_msg(&UdpMsg::MtuProbe {
+				token,
+				size: size as u32,
+				padding: mtu_probe_padding(size),
+			}))
+			.collect();
+		self.outgoing.extend(probes);
+	}
+
+	fn unanswered_mtu_probes(&self) -> impl Iterator<Item = usize> + '_ {
+		MTU_PROBE_SIZES.into_iter().filter(|size| *size > self.probed_mtu)
+	}
+
+	fn accept_connection(&mut self, mtu: usize, now: Instant) {
+		self.state = State::Connected(Box::new(Connection::new(&self.config, mtu, now)));
+		// the queued msgs were already checked when they got sent, so this can't fail
+		for (channel_id, data) in std::mem::take(&mut self.queued_msgs) {
+			let _ = self.send(&data, channel_id, now);
+		}
+		// they were waiting for the connection, not for a flush
+		self.flush(now);
+	}
+
+	fn receive_packet(&mut self, packet: Packet, now: Instant) {
+		if let State::Connected(connection) = &mut self.state {
+			for (channel_id, data) in connection.receive(packet, now) {
+				self.events.push_back(ClientTransportEvent::NewMsg(channel_id, data));
+			}
+			if let Some(packet) = connection.urgent_ack(now) {
+				self.outgoing.push(serialize_udp_msg(&UdpMsg::Packet(packet)));
+			}
+		}
+	}
+}
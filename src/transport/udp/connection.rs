@@ -0,0 +1,1032 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::transport::{ChannelId, Coalescing, DeliveryMode, DisconnectReason, MAX_MSG_SIZE};
+use super::UdpConfig;
+
+pub(crate) const UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+// used until the first rtt got measured
+const INITIAL_RESEND_TIMEOUT: Duration = Duration::from_millis(100);
+// acks may wait for the next update of the receiver, so the timeout has to cover that on top of the rtt
+const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(50);
+// upper bound of the backoff of msgs that got lost repeatedly
+const MAX_RESEND_TIMEOUT: Duration = Duration::from_secs(2);
+// incomplete msgs of unreliable channels are dropped after this, since their missing fragments may never arrive
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(5);
+// reliable bytes that may be unacked at once, the window grows with every ack and halves on losses
+const INITIAL_CONGESTION_WINDOW: usize = 32 * 1024;
+const MIN_CONGESTION_WINDOW: usize = 8 * 1024;
+// also serves as the flow window, since larger bursts overrun the default socket receive buffers
+const MAX_CONGESTION_WINDOW: usize = 128 * 1024;
+// NOTE: Has to stay well below half of the u16 range, otherwise wrapped ids become ambiguous
+const RELIABLE_WINDOW_SIZE: u16 = 1024;
+const ACK_BITS: u16 = u32::BITS as u16;
+// A burst of fragments easily outgrows the ack bits, so the acks for its first packets may only arrive
+// after many newer packets were sent. Has to stay well below half of the u16 range as well.
+const SENT_PACKETS_KEPT: u16 = 1024;
+// upper bounds of the bincode encoding, including the UdpMsg tag
+const PACKET_HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 8;
+const PACKET_MSG_HEADER_SIZE: usize = 1 + 2 + 1 + 6 + 8;
+// smallest mtu a connection can use, which every ipv4 path has to support
+pub(crate) const MIN_MTU: usize = 508;
+const MAX_FRAGMENT_SIZE: usize = MAX_MSG_SIZE - PACKET_HEADER_SIZE - PACKET_MSG_HEADER_SIZE;
+
+// larger msgs get split into fragments of this size, so that each fragment fits into a packet on its own
+pub(crate) fn fragment_size(mtu: usize) -> usize {
+	mtu.clamp(MIN_MTU, MAX_MSG_SIZE) - PACKET_HEADER_SIZE - PACKET_MSG_HEADER_SIZE
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Packet {
+	sequence: u16,
+	ack: u16,
+	ack_bits: u32,
+	msgs: Vec<PacketMsg>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PacketMsg {
+	channel_id: ChannelId,
+	id: u16,
+	fragment: Option<Fragment>,
+	data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Fragment {
+	group_id: u16,
+	index: u16,
+	count: u16,
+}
+
+struct FragmentGroup {
+	// by index, only the received ones take up memory
+	fragments: HashMap<u16, Vec<u8>>,
+	count: u16,
+	// fragments of reliable channels are guaranteed to arrive, so only unreliable ones expire
+	expires: Option<Instant>,
+}
+
+// a msg as it travels through a channel, either whole or as one of its fragments
+type MsgPart = (Option<Fragment>, Vec<u8>);
+
+fn sequence_greater_than(a: u16, b: u16) -> bool {
+	a != b && a.wrapping_sub(b) < u16::MAX / 2
+}
+
+struct PendingMsg {
+	id: u16,
+	fragment: Option<Fragment>,
+	data: Vec<u8>,
+	last_sent: Option<Instant>,
+	// every resend doubles the timeout of the next one
+	resends: u32,
+	// sent and neither acked nor considered lost yet, so it occupies the congestion window
+	in_flight: bool,
+}
+
+#[derive(Default)]
+struct ReliableSendStream {
+	next_id: u16,
+	unacked: VecDeque<PendingMsg>,
+}
+
+impl ReliableSendStream {
+	fn push(&mut self, (fragment, data): MsgPart) {
+		self.unacked.push_back(PendingMsg {
+			id: self.next_id,
+			fragment,
+			data,
+			last_sent: None,
+			resends: 0,
+			in_flight: false,
+		});
+		self.next_id = self.next_id.wrapping_add(1);
+	}
+
+	// None if the msg was already acked before
+	fn ack(&mut self, id: u16) -> Option<PendingMsg> {
+		let index = self.unacked.iter().position(|msg| msg.id == id)?;
+		self.unacked.remove(index)
+	}
+
+	// the receiver only buffers msgs inside of its window, so newer msgs have to wait for older ones to be acked
+	fn sendable_msgs(&mut self) -> impl Iterator<Item = &mut PendingMsg> {
+		let oldest_id = self.unacked.front().map(|msg| msg.id).unwrap_or(self.next_id);
+		self.unacked.iter_mut().take_while(move |msg| msg.id.wrapping_sub(oldest_id) < RELIABLE_WINDOW_SIZE)
+	}
+}
+
+#[derive(Default)]
+struct ReliableReceiveStream {
+	next_id: u16,
+	// None marks msgs that were already delivered out of order
+	buffered: HashMap<u16, Option<MsgPart>>,
+}
+
+impl ReliableReceiveStream {
+	fn receive(&mut self, id: u16, part: MsgPart, ordered: bool, delivered: &mut Vec<MsgPart>) {
+		let already_received = id.wrapping_sub(self.next_id) >= RELIABLE_WINDOW_SIZE || self.buffered.contains_key(&id);
+		if already_received {
+			return;
+		}
+
+		if ordered {
+			self.buffered.insert(id, Some(part));
+		}
+		else {
+			delivered.push(part);
+			self.buffered.insert(id, None);
+		}
+
+		while let Some(msg) = self.buffered.remove(&self.next_id) {
+			delivered.extend(msg);
+			self.next_id = self.next_id.wrapping_add(1);
+		}
+	}
+}
+
+// Estimates the rtt and limits the unacked reliable bytes like tcp does, so that a large burst
+// doesn't overrun the buffers along the path and then get resent as a whole.
+struct CongestionControl {
+	smoothed_rtt: Option<Duration>,
+	rtt_variance: Duration,
+	resend_timeout: Duration,
+	window: usize,
+	slow_start_threshold: usize,
+	bytes_in_flight: usize,
+	// a burst usually loses many msgs at once, so the window only shrinks once per rtt
+	last_window_cut: Option<Instant>,
+}
+
+impl CongestionControl {
+	fn new() -> Self {
+		Self {
+			smoothed_rtt: None,
+			rtt_variance: Duration::ZERO,
+			resend_timeout: INITIAL_RESEND_TIMEOUT,
+			window: INITIAL_CONGESTION_WINDOW,
+			slow_start_threshold: MAX_CONGESTION_WINDOW,
+			bytes_in_flight: 0,
+			last_window_cut: None,
+		}
+	}
+
+	// NOTE: Resent msgs travel in new packets with their own sequence, so the samples are never ambiguous
+	fn on_rtt_sample(&mut self, rtt: Duration) {
+		match self.smoothed_rtt {
+			None => {
+				self.smoothed_rtt = Some(rtt);
+				self.rtt_variance = rtt / 2;
+			},
+			Some(smoothed_rtt) => {
+				let deviation = smoothed_rtt.abs_diff(rtt);
+				self.rtt_variance = (self.rtt_variance * 3 + deviation) / 4;
+				self.smoothed_rtt = Some((smoothed_rtt * 7 + rtt) / 8);
+			},
+		}
+		let resend_timeout = self.smoothed_rtt.unwrap() + self.rtt_variance * 4;
+		self.resend_timeout = resend_timeout.clamp(MIN_RESEND_TIMEOUT, MAX_RESEND_TIMEOUT);
+	}
+
+	fn resend_timeout(&self, resends: u32) -> Duration {
+		self.resend_timeout.saturating_mul(1 << resends.min(8)).min(MAX_RESEND_TIMEOUT)
+	}
+
+	// the first msg always fits, so that msgs larger than the window don't stall
+	fn can_send(&self, size: usize) -> bool {
+		self.bytes_in_flight == 0 || self.bytes_in_flight + size <= self.window
+	}
+
+	fn on_send(&mut self, size: usize) {
+		self.bytes_in_flight += size;
+	}
+
+	fn on_ack(&mut self, size: usize, in_flight: bool, mtu: usize) {
+		if in_flight {
+			self.bytes_in_flight -= size;
+		}
+		let growth = if self.window < self.slow_start_threshold {
+			size
+		}
+		else {
+			(mtu * size / self.window).max(1)
+		};
+		self.window = (self.window + growth).min(MAX_CONGESTION_WINDOW);
+	}
+
+	fn on_loss(&mut self, size: usize, now: Instant) {
+		self.bytes_in_flight -= size;
+		let cut_due = self.last_window_cut.is_none_or(|last_cut| now.duration_since(last_cut) >= self.smoothed_rtt.unwrap_or(self.resend_timeout));
+		if cut_due {
+			self.slow_start_threshold = (self.window / 2).max(MIN_CONGESTION_WINDOW);
+			self.window = self.slow_start_threshold;
+			self.last_window_cut = Some(now);
+		}
+	}
+}
+
+// Every channel keeps its own ids and buffers, so a stalled channel never blocks the others
+enum Channel {
+	Unreliable,
+	UnreliableSequenced {
+		next_send_id: u16,
+		newest_received_id: Option<u16>,
+	},
+	Reliable {
+		ordered: bool,
+		send_stream: ReliableSendStream,
+		receive_stream: ReliableReceiveStream,
+	},
+}
+
+impl Channel {
+	fn new(delivery: DeliveryMode) -> Self {
+		match delivery {
+			DeliveryMode::Unreliable => Self::Unreliable,
+			DeliveryMode::UnreliableSequenced => Self::UnreliableSequenced {
+				next_send_id: 0,
+				newest_received_id: None,
+			},
+			DeliveryMode::ReliableUnordered | DeliveryMode::ReliableOrdered => Self::Reliable {
+				ordered: delivery == DeliveryMode::ReliableOrdered,
+				send_stream: ReliableSendStream::default(),
+				receive_stream: ReliableReceiveStream::default(),
+			},
+		}
+	}
+}
+
+// Reliability layer of a single udp peer. It doesn't touch the socket itself,
+// the transports feed it received packets and send whatever packets it returns.
+pub(crate) struct Connection {
+	local_sequence: u16,
+	remote_sequence: Option<u16>,
+	received_bits: u32,
+	ack_pending: bool,
+	// packets received since our last ack, acks can't wait for the next update once they fill the ack bits
+	unacked_packets: u16,
+	// when every sent packet that isn't acked yet went out and which reliable msgs it carried
+	sent_packets: HashMap<u16, (Instant, Vec<(ChannelId, u16)>)>,
+	channels: Vec<Channel>,
+	next_fragment_group_id: u16,
+	fragment_groups: HashMap<(ChannelId, u16), FragmentGroup>,
+	// the fragments of incomplete msgs per channel
+	buffered_fragments: Vec<usize>,
+	max_msg_size: usize,
+	// the peer broke the protocol, e.g. by keeping more fragments incomplete than its reliable window allows
+	broken: bool,
+	// upper bound of every sent packet, negotiated during the handshake
+	mtu: usize,
+	congestion: CongestionControl,
+	coalescing: Coalescing,
+	// msgs that wait for the next flush, so that they can be packed together
+	queued_msgs: Vec<(ChannelId, Vec<MsgPart>)>,
+	queued_since: Option<Instant>,
+	heartbeat_interval: Duration,
+	idle_timeout: Duration,
+	last_sent: Instant,
+	last_received: Instant,
+}
+
+impl Connection {
+	pub(crate) fn new(config: &UdpConfig, mtu: usize, now: Instant) -> Self {
+		Self {
+			local_sequence: 0,
+			remote_sequence: None,
+			received_bits: 0,
+			ack_pending: false,
+			unacked_packets: 0,
+			sent_packets: HashMap::new(),
+			channels: config.channels.iter().map(|delivery| Channel::new(*delivery)).collect(),
+			next_fragment_group_id: 0,
+			fragment_groups: HashMap::new(),
+			buffered_fragments: vec![0; config.channels.len()],
+			max_msg_size: config.max_msg_size,
+			broken: false,
+			mtu: mtu.clamp(MIN_MTU, MAX_MSG_SIZE),
+			congestion: CongestionControl::new(),
+			coalescing: config.coalescing,
+			queued_msgs: Vec::new(),
+			queued_since: None,
+			heartbeat_interval: config.heartbeat_interval,
+			idle_timeout: config.idle_timeout,
+			last_sent: now,
+			last_received: now,
+		}
+	}
+
+	pub(crate) fn send(&mut self, data: &[u8], channel_id: ChannelId, now: Instant) -> Vec<Packet> {
+		let parts = self.split_into_parts(data);
+		self.queued_msgs.push((channel_id, parts));
+		self.queued_since.get_or_insert(now);
+
+		match self.coalescing {
+			Coalescing::Disabled => self.flush(now),
+			Coalescing::Manual | Coalescing::Interval(_) => Vec::new(),
+		}
+	}
+
+	// sends the queued msgs, packed into as few packets as possible
+	pub(crate) fn flush(&mut self, now: Instant) -> Vec<Packet> {
+		let mut msgs = self.take_queued_msgs();
+		msgs.extend(self.reliable_msgs(now));
+		if !msgs.is_empty() {
+			self.last_sent = now;
+		}
+		self.pack_msgs(msgs, now)
+	}
+
+	// reliable msgs only get handed to their stream, which sends them along with the ones that need a resend
+	fn take_queued_msgs(&mut self) -> Vec<PacketMsg> {
+		self.queued_since = None;
+
+		let mut msgs = Vec::new();
+		for (channel_id, parts) in std::mem::take(&mut self.queued_msgs) {
+			let id = match &mut self.channels[channel_id as usize] {
+				Channel::Unreliable => 0,
+				// all fragments of a msg share its id, so that they aren't dropped as outdated
+				Channel::UnreliableSequenced { next_send_id, .. } => {
+					let id = *next_send_id;
+					*next_send_id = next_send_id.wrapping_add(1);
+					id
+				},
+				Channel::Reliable { send_stream, .. } => {
+					for part in parts {
+						send_stream.push(part);
+					}
+					continue;
+				},
+			};
+
+			msgs.extend(parts.into_iter().map(|(fragment, data)| PacketMsg {
+				channel_id,
+				id,
+				fragment,
+				data,
+			}));
+		}
+		msgs
+	}
+
+	pub(crate) fn mtu(&self) -> usize {
+		self.mtu
+	}
+
+	fn split_into_parts(&mut self, data: &[u8]) -> Vec<MsgPart> {
+		let fragment_size = fragment_size(self.mtu);
+		if data.len() <= fragment_size {
+			return vec![(None, data.to_vec())];
+		}
+
+		let group_id = self.next_fragment_group_id;
+		self.next_fragment_group_id = self.next_fragment_group_id.wrapping_add(1);
+		let count = data.len().div_ceil(fragment_size) as u16;
+		data.chunks(fragment_size)
+			.enumerate()
+			.map(|(index, chunk)| {
+				let fragment = Fragment {
+					group_id,
+					index: index as u16,
+					count,
+				};
+				(Some(fragment), chunk.to_vec())
+			})
+			.collect()
+	}
+
+	// Resends reliable msgs that didn't get acked in time and acks received packets if nothing else did.
+	// An empty packet also serves as the heartbeat that keeps an idle connection alive.
+	pub(crate) fn update(&mut self, now: Instant) -> Vec<Packet> {
+		let buffered_fragments = &mut self.buffered_fragments;
+		self.fragment_groups.retain(|(channel_id, _group_id), group| {
+			let expired = group.expires.is_some_and(|expires| now >= expires);
+			if expired {
+				buffered_fragments[*channel_id as usize] -= group.fragments.len();
+			}
+			!expired
+		});
+
+		let flush_due = match self.coalescing {
+			Coalescing::Interval(interval) => self.queued_since.is_some_and(|queued_since| now.duration_since(queued_since) >= interval),
+			Coalescing::Disabled | Coalescing::Manual => false,
+		};
+		let mut msgs = if flush_due {
+			self.take_queued_msgs()
+		}
+		else {
+			Vec::new()
+		};
+		msgs.extend(self.reliable_msgs(now));
+		let mut packets = self.pack_msgs(msgs, now);
+		let heartbeat_due = now.duration_since(self.last_sent) >= self.heartbeat_interval;
+		if packets.is_empty() && (self.ack_pending || heartbeat_due) {
+			packets.push(self.create_packet(Vec::new(), now));
+		}
+		if !packets.is_empty() {
+			self.last_sent = now;
+		}
+		packets
+	}
+
+	pub(crate) fn is_timed_out(&self, now: Instant) -> bool {
+		now.duration_since(self.last_received) >= self.idle_timeout
+	}
+
+	// why the endpoint has to close the connection, if it has to
+	pub(crate) fn failure(&self, now: Instant) -> Option<DisconnectReason> {
+		if self.broken {
+			Some(DisconnectReason::ConnectionLost)
+		}
+		else if self.is_timed_out(now) {
+			Some(DisconnectReason::TimedOut)
+		}
+		else {
+			None
+		}
+	}
+
+	pub(crate) fn receive(&mut self, packet: Packet, now: Instant) -> Vec<(ChannelId, Vec<u8>)> {
+		self.last_received = now;
+		self.process_acks(packet.ack, packet.ack_bits, now);
+
+		if !self.record_received_sequence(packet.sequence) {
+			return Vec::new();
+		}
+
+		let mut delivered = Vec::new();
+		for msg in packet.msgs {
+			// msgs on channels we don't know about can't be interpreted
+			let Some(channel) = self.channels.get_mut(msg.channel_id as usize) else {
+				continue;
+			};
+
+			let mut parts = Vec::new();
+			let reliable = match channel {
+				Channel::Unreliable => {
+					parts.push((msg.fragment, msg.data));
+					false
+				},
+				Channel::UnreliableSequenced { newest_received_id, .. } => {
+					let is_newest = match newest_received_id {
+						Some(newest_id) => sequence_greater_than(msg.id, *newest_id) || (msg.id == *newest_id && msg.fragment.is_some()),
+						None => true,
+					};
+					if is_newest {
+						*newest_received_id = Some(msg.id);
+						parts.push((msg.fragment, msg.data));
+					}
+					false
+				},
+				Channel::Reliable { ordered, receive_stream, .. } => {
+					self.ack_pending = true;
+					receive_stream.receive(msg.id, (msg.fragment, msg.data), *ordered, &mut parts);
+					true
+				},
+			};
+
+			for (fragment, data) in parts {
+				match fragment {
+					None => delivered.push((msg.channel_id, data)),
+					Some(fragment) => {
+						if let Some(data) = self.reassemble(msg.channel_id, fragment, data, reliable, now) {
+							delivered.push((msg.channel_id, data));
+						}
+					},
+				}
+			}
+		}
+		if self.ack_pending {
+			self.unacked_packets += 1;
+		}
+		delivered
+	}
+
+	// an ack that has to be sent right away, since the packets it covers would otherwise fall out of the ack bits
+	pub(crate) fn urgent_ack(&mut self, now: Instant) -> Option<Packet> {
+		(self.unacked_packets >= ACK_BITS / 2).then(|| self.create_packet(Vec::new(), now))
+	}
+
+	// returns the whole msg once its last missing fragment arrived
+	fn reassemble(&mut self, channel_id: ChannelId, fragment: Fragment, data: Vec<u8>, reliable: bool, now: Instant) -> Option<Vec<u8>> {
+		// fragments of msgs over the size limit are dropped instead of allocating for them
+		let count = fragment.count as usize;
+		let max_count = self.max_msg_size.div_ceil(fragment_size(self.mtu));
+		if fragment.index >= fragment.count || data.len() > MAX_FRAGMENT_SIZE || count > max_count {
+			return None;
+		}
+
+		let key = (channel_id, fragment.group_id);
+		if let Some(group) = self.fragment_groups.get(&key) {
+			if group.count != fragment.count || group.fragments.contains_key(&fragment.index) {
+				return None;
+			}
+		}
+
+		// The sender keeps at most a window of reliable msgs unacked, so only a misbehaving peer leaves more fragments incomplete.
+		// Unreliable fragments over the limit are simply dropped, like lost ones.
+		let buffered_fragments = &mut self.buffered_fragments[channel_id as usize];
+		if *buffered_fragments >= RELIABLE_WINDOW_SIZE as usize + max_count {
+			self.broken |= reliable;
+			return None;
+		}
+		*buffered_fragments += 1;
+
+		let group = self.fragment_groups.entry(key).or_insert_with(|| FragmentGroup {
+			fragments: HashMap::new(),
+			count: fragment.count,
+			expires: (!reliable).then_some(now + FRAGMENT_TIMEOUT),
+		});
+		group.fragments.insert(fragment.index, data);
+		if group.fragments.len() < count {
+			return None;
+		}
+
+		let mut group = self.fragment_groups.remove(&key)?;
+		*buffered_fragments -= count;
+		let msg: Vec<u8> = (0..fragment.count).flat_map(|index| group.fragments.remove(&index).unwrap()).collect();
+		(msg.len() <= self.max_msg_size).then_some(msg)
+	}
+
+	// msgs that don't fit into the congestion window stay unsent until acks make room for them
+	fn reliable_msgs(&mut self, now: Instant) -> Vec<PacketMsg> {
+		// msgs that didn't get acked in time are considered lost, which frees their place in the window
+		for channel in self.channels.iter_mut() {
+			let Channel::Reliable { send_stream, .. } = channel else {
+				continue;
+			};
+			for pending_msg in send_stream.unacked.iter_mut().filter(|msg| msg.in_flight) {
+				let last_sent = pending_msg.last_sent.unwrap();
+				if now.duration_since(last_sent) >= self.congestion.resend_timeout(pending_msg.resends) {
+					pending_msg.in_flight = false;
+					self.congestion.on_loss(pending_msg.data.len(), now);
+				}
+			}
+		}
+
+		let mut msgs = Vec::new();
+		'channels: for (channel_id, channel) in self.channels.iter_mut().enumerate() {
+			let Channel::Reliable { send_stream, .. } = channel else {
+				continue;
+			};
+
+			for pending_msg in send_stream.sendable_msgs().filter(|msg| !msg.in_flight) {
+				if !self.congestion.can_send(pending_msg.data.len()) {
+					break 'channels;
+				}
+				self.congestion.on_send(pending_msg.data.len());
+				if pending_msg.last_sent.is_some() {
+					pending_msg.resends += 1;
+				}
+				pending_msg.last_sent = Some(now);
+				pending_msg.in_flight = true;
+				msgs.push(PacketMsg {
+					channel_id: channel_id as ChannelId,
+					id: pending_msg.id,
+					fragment: pending_msg.fragment,
+					data: pending_msg.data.clone(),
+				});
+			}
+		}
+		msgs
+	}
+
+	// Packs as many msgs into each packet as fit, since every packet can only ack a limited number of previous packets
+	fn pack_msgs(&mut self, msgs: Vec<PacketMsg>, now: Instant) -> Vec<Packet> {
+		let mut packets = Vec::new();
+		let mut packet_msgs = Vec::new();
+		let mut packet_size = PACKET_HEADER_SIZE;
+		for msg in msgs {
+			let msg_size = PACKET_MSG_HEADER_SIZE + msg.data.len();
+			if !packet_msgs.is_empty() && packet_size + msg_size > self.mtu {
+				packets.push(self.create_packet(std::mem::take(&mut packet_msgs), now));
+				packet_size = PACKET_HEADER_SIZE;
+			}
+			packet_size += msg_size;
+			packet_msgs.push(msg);
+		}
+		if !packet_msgs.is_empty() {
+			packets.push(self.create_packet(packet_msgs, now));
+		}
+		packets
+	}
+
+	fn create_packet(&mut self, msgs: Vec<PacketMsg>, now: Instant) -> Packet {
+		let sequence = self.local_sequence;
+		self.local_sequence = self.local_sequence.wrapping_add(1);
+		// packets this old are considered lost, their msgs get resent by the timeout instead
+		self.sent_packets.remove(&sequence.wrapping_sub(SENT_PACKETS_KEPT));
+		self.ack_pending = false;
+		self.unacked_packets = 0;
+
+		let reliable_msgs: Vec<_> = msgs.iter()
+			.filter(|msg| matches!(self.channels[msg.channel_id as usize], Channel::Reliable { .. }))
+			.map(|msg| (msg.channel_id, msg.id))
+			.collect();
+		if !reliable_msgs.is_empty() {
+			self.sent_packets.insert(sequence, (now, reliable_msgs));
+		}
+
+		Packet {
+			sequence,
+			ack: self.remote_sequence.unwrap_or(u16::MAX),
+			ack_bits: self.received_bits,
+			msgs,
+		}
+	}
+
+	fn process_acks(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+		// older packets may have been acked late, only the newest one is sure to measure the rtt
+		if let Some((sent, _)) = self.sent_packets.get(&ack) {
+			self.congestion.on_rtt_sample(now.duration_since(*sent));
+		}
+
+		let acked_sequences = std::iter::once(ack).chain(
+			(0..ACK_BITS)
+				.filter(|bit| ack_bits & (1 << bit) != 0)
+				.map(|bit| ack.wrapping_sub(bit + 1))
+		);
+
+		for sequence in acked_sequences {
+			if let Some((_, msgs)) = self.sent_packets.remove(&sequence) {
+				for (channel_id, id) in msgs {
+					if let Some(Channel::Reliable { send_stream, .. }) = self.channels.get_mut(channel_id as usize) {
+						if let Some(msg) = send_stream.ack(id) {
+							self.congestion.on_ack(msg.data.len(), msg.in_flight, self.mtu);
+						}
+					}
+				}
+			}
+		}
+	}
+
+	// returns false for duplicated packets
+	fn record_received_sequence(&mut self, sequence: u16) -> bool {
+		let Some(remote_sequence) = self.remote_sequence else {
+			self.remote_sequence = Some(sequence);
+			return true;
+		};
+
+		if sequence_greater_than(sequence, remote_sequence) {
+			let shift = sequence.wrapping_sub(remote_sequence);
+			self.received_bits = if shift > ACK_BITS {
+				0
+			}
+			else {
+				self.received_bits.checked_shl(shift as u32).unwrap_or(0) | (1 << (shift - 1))
+			};
+			self.remote_sequence = Some(sequence);
+			true
+		}
+		else {
+			let age = remote_sequence.wrapping_sub(sequence);
+			if age == 0 {
+				return false;
+			}
+			if age <= ACK_BITS {
+				let bit = 1 << (age - 1);
+				if self.received_bits & bit != 0 {
+					return false;
+				}
+				self.received_bits |= bit;
+			}
+			true
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the kernel accounts for more than the payload of every buffered datagram
+	const DATAGRAM_OVERHEAD: usize = 768;
+	const RECEIVE_BUFFER_SIZE: usize = 208 * 1024;
+	const DATAGRAMS_READ_PER_MS: usize = 1;
+
+	// behaves like the receive buffer of a socket, which drops datagrams once it's full
+	#[derive(Default)]
+	struct Link {
+		buffered: VecDeque<Vec<u8>>,
+		buffered_size: usize,
+		dropped: usize,
+	}
+
+	impl Link {
+		fn send(&mut self, packets: impl IntoIterator<Item = Packet>) {
+			for packet in packets {
+				let datagram = bincode::serialize(&packet).unwrap();
+				let size = datagram.len() + DATAGRAM_OVERHEAD;
+				if self.buffered_size + size > RECEIVE_BUFFER_SIZE {
+					self.dropped += 1;
+					continue;
+				}
+				self.buffered_size += size;
+				self.buffered.push_back(datagram);
+			}
+		}
+
+		fn receive(&mut self) -> Vec<Packet> {
+			let mut packets = Vec::new();
+			while packets.len() < DATAGRAMS_READ_PER_MS {
+				let Some(datagram) = self.buffered.pop_front() else {
+					break;
+				};
+				self.buffered_size -= datagram.len() + DATAGRAM_OVERHEAD;
+				packets.push(bincode::deserialize(&datagram).unwrap());
+			}
+			packets
+		}
+	}
+
+	fn connection_pair(channels: Vec<DeliveryMode>) -> (Connection, Connection, Instant) {
+		let config = UdpConfig {
+			channels,
+			..UdpConfig::default()
+		};
+		let now = Instant::now();
+		(Connection::new(&config, config.mtu, now), Connection::new(&config, config.mtu, now), now)
+	}
+
+	fn send_one(connection: &mut Connection, data: &[u8], channel_id: ChannelId, now: Instant) -> Packet {
+		let mut packets = connection.send(data, channel_id, now);
+		assert_eq!(packets.len(), 1);
+		packets.pop().unwrap()
+	}
+
+	fn deliver(packets: impl IntoIterator<Item = Packet>, receiver: &mut Connection, now: Instant) -> Vec<(ChannelId, Vec<u8>)> {
+		packets.into_iter().flat_map(|packet| receiver.receive(packet, now)).collect()
+	}
+
+	fn unacked_ids(connection: &Connection, channel_id: ChannelId) -> Vec<u16> {
+		let Channel::Reliable { send_stream, .. } = &connection.channels[channel_id as usize] else {
+			panic!("expected a reliable channel");
+		};
+		send_stream.unacked.iter().map(|msg| msg.id).collect()
+	}
+
+	fn sent_msg_count(packets: &[Packet]) -> usize {
+		packets.iter().map(|packet| packet.msgs.len()).sum()
+	}
+
+	#[test]
+	fn acks_cover_older_packets_with_their_bits() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::ReliableOrdered]);
+		let first = send_one(&mut a, b"0", 0, now);
+		let _lost = send_one(&mut a, b"1", 0, now);
+		let third = send_one(&mut a, b"2", 0, now);
+		deliver([first, third], &mut b, now);
+
+		let ack = b.update(now).pop().unwrap();
+		assert_eq!(ack.ack, 2);
+		assert_eq!(ack.ack_bits & 0b11, 0b10);
+		deliver([ack], &mut a, now);
+		assert_eq!(unacked_ids(&a, 0), [1]);
+	}
+
+	#[test]
+	fn lost_reliable_msgs_get_resent_with_backoff() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::ReliableOrdered]);
+		let _lost = send_one(&mut a, b"msg", 0, now);
+		assert_eq!(sent_msg_count(&a.update(now + INITIAL_RESEND_TIMEOUT / 2)), 0);
+		let lost_again = a.update(now + INITIAL_RESEND_TIMEOUT);
+		assert_eq!(sent_msg_count(&lost_again), 1);
+
+		// the second resend waits twice as long
+		assert_eq!(sent_msg_count(&a.update(now + INITIAL_RESEND_TIMEOUT * 2)), 0);
+		let resent = a.update(now + INITIAL_RESEND_TIMEOUT * 3);
+		assert_eq!(sent_msg_count(&resent), 1);
+
+		let now = now + INITIAL_RESEND_TIMEOUT * 3;
+		assert_eq!(deliver(resent, &mut b, now), [(0, b"msg".to_vec())]);
+		deliver(b.update(now), &mut a, now);
+		assert!(unacked_ids(&a, 0).is_empty());
+		assert_eq!(sent_msg_count(&a.update(now + MAX_RESEND_TIMEOUT)), 0);
+	}
+
+	#[test]
+	fn rtt_samples_adjust_the_resend_timeout() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::ReliableOrdered]);
+		let packet = send_one(&mut a, b"msg", 0, now);
+		let now = now + Duration::from_millis(100);
+		deliver([packet], &mut b, now);
+		deliver(b.update(now), &mut a, now);
+		assert_eq!(a.congestion.smoothed_rtt, Some(Duration::from_millis(100)));
+		// rtt plus four times its variance, which starts at half of the first sample
+		assert_eq!(a.congestion.resend_timeout(0), Duration::from_millis(300));
+	}
+
+	#[test]
+	fn ordered_channels_wait_for_missing_msgs_without_blocking_others() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::ReliableOrdered, DeliveryMode::ReliableUnordered]);
+		let ordered_first = send_one(&mut a, b"0", 0, now);
+		let ordered_second = send_one(&mut a, b"1", 0, now);
+		let unordered_first = send_one(&mut a, b"0", 1, now);
+		let unordered_second = send_one(&mut a, b"1", 1, now);
+
+		assert_eq!(deliver([ordered_second, unordered_second], &mut b, now), [(1, b"1".to_vec())]);
+		assert_eq!(deliver([ordered_first], &mut b, now), [(0, b"0".to_vec()), (0, b"1".to_vec())]);
+		assert_eq!(deliver([unordered_first], &mut b, now), [(1, b"0".to_vec())]);
+	}
+
+	#[test]
+	fn sequenced_channels_drop_outdated_msgs() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::UnreliableSequenced]);
+		let first = send_one(&mut a, b"0", 0, now);
+		let second = send_one(&mut a, b"1", 0, now);
+		assert_eq!(deliver([second, first], &mut b, now), [(0, b"1".to_vec())]);
+	}
+
+	#[test]
+	fn duplicated_packets_are_ignored() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::Unreliable, DeliveryMode::ReliableUnordered]);
+		for channel_id in [0, 1] {
+			let packet = send_one(&mut a, b"msg", channel_id, now);
+			let duplicate = bincode::deserialize(&bincode::serialize(&packet).unwrap()).unwrap();
+			assert_eq!(deliver([packet, duplicate], &mut b, now), [(channel_id, b"msg".to_vec())]);
+		}
+	}
+
+	#[test]
+	fn idle_connections_send_heartbeats() {
+		let (mut a, mut b, start) = connection_pair(vec![DeliveryMode::Unreliable]);
+		let UdpConfig { heartbeat_interval, idle_timeout, .. } = UdpConfig::default();
+		assert!(a.update(start + heartbeat_interval / 2).is_empty());
+		let heartbeat_sent = start + heartbeat_interval;
+		let heartbeats = a.update(heartbeat_sent);
+		assert!(heartbeats.len() == 1 && heartbeats[0].msgs.is_empty());
+
+		// sent msgs count as well
+		send_one(&mut a, b"msg", 0, heartbeat_sent + heartbeat_interval / 2);
+		assert!(a.update(heartbeat_sent + heartbeat_interval).is_empty());
+
+		assert!(b.is_timed_out(start + idle_timeout));
+		deliver(heartbeats, &mut b, heartbeat_sent);
+		assert!(!b.is_timed_out(start + idle_timeout));
+	}
+
+	#[test]
+	fn large_msgs_get_split_into_fragments_and_reassembled() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::Unreliable, DeliveryMode::ReliableOrdered]);
+		let msg: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+		for channel_id in [0, 1] {
+			let packets = a.send(&msg, channel_id, now);
+			assert!(packets.len() > 1);
+			assert!(packets.iter().all(|packet| bincode::serialize(packet).unwrap().len() <= UdpConfig::default().mtu));
+			// fragments may arrive in any order
+			assert_eq!(deliver(packets.into_iter().rev(), &mut b, now), [(channel_id, msg.clone())]);
+		}
+	}
+
+	#[test]
+	fn fragments_of_msgs_over_the_size_limit_are_dropped() {
+		let (mut a, _b, now) = connection_pair(vec![DeliveryMode::Unreliable]);
+		let config = UdpConfig {
+			channels: vec![DeliveryMode::Unreliable],
+			max_msg_size: 2000,
+			..UdpConfig::default()
+		};
+		let mut b = Connection::new(&config, config.mtu, now);
+		assert!(deliver(a.send(&[0; 5000], 0, now), &mut b, now).is_empty());
+		assert!(b.fragment_groups.is_empty());
+	}
+
+	#[test]
+	fn incomplete_unreliable_msgs_expire() {
+		let (mut a, mut b, now) = connection_pair(vec![DeliveryMode::Unreliable, DeliveryMode::ReliableOrdered]);
+		for channel_id in [0, 1] {
+			let mut packets = a.send(&[0; 5000], channel_id, now);
+			packets.pop();
+			assert!(deliver(packets, &mut b, now).is_empty());
+		}
+		assert_eq!(b.fragment_groups.len(), 2);
+
+		// the missing fragments of reliable msgs are guaranteed to arrive eventually
+		b.update(now + FRAGMENT_TIMEOUT);
+		assert_eq!(b.fragment_groups.keys().collect::<Vec<_>>(), [&(1, 1)]);
+	}
+
+	#[test]
+	fn peers_that_leave_too_many_reliable_fragments_incomplete_get_disconnected() {
+		let (_a, mut b, now) = connection_pair(vec![DeliveryMode::ReliableUnordered]);
+		let max_buffered = RELIABLE_WINDOW_SIZE as usize + UdpConfig::default().max_msg_size.div_ceil(fragment_size(b.mtu()));
+		// every msg only gets its first fragment, so none of them ever completes
+		let first_fragment = |id: u16| Packet {
+			sequence: id,
+			ack: u16::MAX,
+			ack_bits: 0,
+			msgs: vec![PacketMsg {
+				channel_id: 0,
+				id,
+				fragment: Some(Fragment {
+					group_id: id,
+					index: 0,
+					count: 2,
+				}),
+				data: vec![0; 100],
+			}],
+		};
+		for id in 0..max_buffered as u16 {
+			b.receive(first_fragment(id), now);
+		}
+		assert_eq!(b.fragment_groups.len(), max_buffered);
+		assert_eq!(b.failure(now), None);
+
+		b.receive(first_fragment(max_buffered as u16), now);
+		assert_eq!(b.fragment_groups.len(), max_buffered);
+		assert_eq!(b.failure(now), Some(DisconnectReason::ConnectionLost));
+	}
+
+	fn coalescing_connection(coalescing: Coalescing, now: Instant) -> Connection {
+		let config = UdpConfig {
+			channels: vec![DeliveryMode::Unreliable, DeliveryMode::ReliableOrdered],
+			coalescing,
+			..UdpConfig::default()
+		};
+		Connection::new(&config, config.mtu, now)
+	}
+
+	#[test]
+	fn queued_msgs_get_packed_together_on_flush() {
+		let (_a, mut b, now) = connection_pair(vec![DeliveryMode::Unreliable, DeliveryMode::ReliableOrdered]);
+		let mut a = coalescing_connection(Coalescing::Manual, now);
+		for i in 0..10u8 {
+			assert!(a.send(&[i], i % 2, now).is_empty());
+		}
+		let packets = a.flush(now);
+		assert_eq!(packets.len(), 1);
+
+		let received = deliver(packets, &mut b, now);
+		for channel_id in [0, 1] {
+			let msgs: Vec<u8> = received.iter().filter(|(id, _data)| *id == channel_id).map(|(_id, data)| data[0]).collect();
+			assert_eq!(msgs, (channel_id..10).step_by(2).collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn packed_msgs_stay_within_the_mtu() {
+		let now = Instant::now();
+		let mut a = coalescing_connection(Coalescing::Manual, now);
+		for _ in 0..10 {
+			a.send(&[0; 500], 0, now);
+		}
+		let packets = a.flush(now);
+		// two of them fit into a packet
+		assert_eq!(packets.len(), 5);
+		assert!(packets.iter().all(|packet| bincode::serialize(packet).unwrap().len() <= UdpConfig::default().mtu));
+	}
+
+	#[test]
+	fn coalescing_intervals_flush_on_update() {
+		let now = Instant::now();
+		let interval = Duration::from_millis(30);
+		let mut a = coalescing_connection(Coalescing::Interval(interval), now);
+		assert!(a.send(b"msg", 0, now).is_empty());
+		assert!(a.update(now + interval / 2).is_empty());
+		assert_eq!(sent_msg_count(&a.update(now + interval)), 1);
+	}
+
+	#[test]
+	fn large_msg_followed_by_many_small_ones_gets_through_a_slow_receiver() {
+		let config = UdpConfig::default();
+		let start = Instant::now();
+		let mut sender = Connection::new(&config, config.mtu, start);
+		let mut receiver = Connection::new(&config, config.mtu, start);
+		let mut to_receiver = Link::default();
+		let mut to_sender = Link::default();
+
+		let large_msg = vec![7; config.max_msg_size - 1024];
+		to_receiver.send(sender.send(&large_msg, 0, start));
+		for i in 0..3000u32 {
+			to_receiver.send(sender.send(&i.to_le_bytes(), 0, start));
+		}
+
+		let mut received = Vec::new();
+		let mut elapsed = Duration::ZERO;
+		while received.len() < 3001 && elapsed < Duration::from_secs(10) {
+			elapsed += Duration::from_millis(1);
+			let now = start + elapsed;
+			for packet in to_receiver.receive() {
+				received.extend(receiver.receive(packet, now).into_iter().map(|(_channel_id, data)| data));
+				to_sender.send(receiver.urgent_ack(now));
+			}
+			for packet in to_sender.receive() {
+				sender.receive(packet, now);
+			}
+			if elapsed.as_millis().is_multiple_of(10) {
+				to_receiver.send(sender.update(now));
+				to_sender.send(receiver.update(now));
+			}
+		}
+
+		assert_eq!(received.len(), 3001, "only {} msgs arrived", received.len());
+		assert!(received[0] == large_msg);
+		for (i, data) in received[1..].iter().enumerate() {
+			assert_eq!(data, &(i as u32).to_le_bytes());
+		}
+		// the window has to keep the receive buffer from overflowing, instead of relying on resends
+		assert_eq!(to_receiver.dropped, 0);
+		assert!(elapsed < Duration::from_secs(2), "took {elapsed:?}");
+	}
+}
@@ -0,0 +1,406 @@
+use std::collections::HashSet;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::io;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::transport::{DEFAULT_MAX_MSG_SIZE, MAX_MSG_SIZE, ChannelId, ClientTransport, ClientTransportEvent, Coalescing, ConnectionRejectedReason, DeliveryMode, DisconnectReason, SendError, ServerConfig, ServerTransport, ServerTransportEvent, wait_on_channel};
+
+mod connection;
+use connection::{MIN_MTU, Packet, fragment_size};
+pub(crate) use connection::UPDATE_INTERVAL;
+mod client_endpoint;
+pub(crate) use client_endpoint::ClientEndpoint;
+mod server_endpoint;
+pub(crate) use server_endpoint::ServerEndpoint;
+#[cfg(test)]
+mod tests;
+
+// NOTE: Has to be increased whenever the format of UdpMsg changes
+const TRANSPORT_VERSION: u32 = 2;
+const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_millis(100);
+// the disconnect msg of a kick gets sent multiple times, since it can't be resent
+const KICK_MSG_REPETITIONS: usize = 3;
+// typical mtus of ethernet, jumbo frames and loopback, minus the ip and udp headers
+const MTU_PROBE_SIZES: [usize; 3] = [1472, 8972, MAX_MSG_SIZE];
+// how long the client waits for answers to its probes, before it finishes the handshake without them
+const MTU_PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+// upper bound of the bincode encoding of an MtuProbe without its padding
+const MTU_PROBE_HEADER_SIZE: usize = 4 + 8 + 4 + 8;
+
+// NOTE: Client and server need to be configured with the same channels
+#[derive(Debug, Clone)]
+pub struct UdpConfig {
+	// the index of each delivery mode is the id of its channel
+	pub channels: Vec<DeliveryMode>,
+	// clients of other applications get rejected during the handshake
+	pub application_id: u64,
+	// version of the msgs of the application, clients with a different version get rejected
+	pub protocol_version: u32,
+	// how often an otherwise idle connection sends packets to keep itself alive
+	pub heartbeat_interval: Duration,
+	// connections that didn't receive anything for this long get disconnected
+	pub idle_timeout: Duration,
+	// Larger msgs are split into fragments and reassembled by the receiver, which drops msgs over its own limit.
+	// NOTE: A msg can have at most u16::MAX fragments, so everything above roughly 29 MiB is rejected anyways
+	pub max_msg_size: usize,
+	// Payload budget of a single datagram. Larger msgs are split into fragments and smaller ones are packed
+	// together up to it. Connections use the smaller budget of client and server.
+	pub mtu: usize,
+	// Lets the client probe during the handshake whether larger datagrams get through, which raises the mtu
+	// of the connection above the configured one. Only happens if client and server both enable it.
+	pub mtu_probing: bool,
+	// queued msgs of a connection are packed into as few packets as the mtu allows
+	// NOTE: Intervals are only checked on every update, so they effectively get rounded up to 10ms
+	pub coalescing: Coalescing,
+}
+
+impl Default for UdpConfig {
+	fn default() -> Self {
+		Self {
+			channels: vec![DeliveryMode::ReliableOrdered],
+			application_id: 0,
+			protocol_version: 0,
+			heartbeat_interval: Duration::from_secs(1),
+			idle_timeout: Duration::from_secs(10),
+			max_msg_size: DEFAULT_MAX_MSG_SIZE,
+			mtu: 1200,
+			mtu_probing: false,
+			coalescing: Coalescing::Disabled,
+		}
+	}
+}
+
+// NOTE: ConnectionRequest has to stay the first variant, so that the version of
+// clients with an incompatible transport can still be checked
+#[derive(Serialize, Deserialize)]
+enum UdpMsg {
+	ConnectionRequest {
+		transport_version: u32,
+		application_id: u64,
+		protocol_version: u32,
+	},
+	Challenge {
+		token: u64,
+	},
+	ChallengeResponse {
+		token: u64,
+		mtu: u32,
+	},
+	ConnectionAccepted {
+		mtu: u32,
+	},
+	ConnectionRejected(ConnectionRejectedReason),
+	Packet(Packet),
+	Disconnected(DisconnectReason),
+	// the padding makes the datagram as large as the probed size, the server echoes it back
+	MtuProbe {
+		token: u64,
+		size: u32,
+		padding: Vec<u8>,
+	},
+	MtuProbeAck {
+		size: u32,
+		padding: Vec<u8>,
+	},
+}
+
+fn mtu_probe_padding(size: usize) -> Vec<u8> {
+	vec![0; size - MTU_PROBE_HEADER_SIZE]
+}
+
+fn serialize_udp_msg(udp_msg: &UdpMsg) -> Vec<u8> {
+	let data = bincode::serialize(udp_msg).unwrap();
+	assert!(data.len() <= MAX_MSG_SIZE, "Sending packets over {MAX_MSG_SIZE} bytes is not supported: see MAX_MSG_SIZE!");
+	data
+}
+
+pub(crate) fn check_msg_size(data: &[u8], config: &UdpConfig) -> Result<(), SendError> {
+	// the negotiated mtu isn't known yet, so this has to assume the smallest fragments
+	let max_size = config.max_msg_size.min(fragment_size(MIN_MTU) * u16::MAX as usize);
+	if data.len() > max_size {
+		return Err(SendError::MsgTooLarge {
+			size: data.len(),
+			max_size,
+		});
+	}
+	Ok(())
+}
+
+pub(crate) fn is_timeout(error: &io::Error) -> bool {
+	matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+pub struct UdpClientTransport {
+	socket: UdpSocket,
+	endpoint: Arc<Mutex<ClientEndpoint>>,
+	transport_msg_receiver: Receiver<ClientTransportEvent>,
+}
+
+impl UdpClientTransport {
+	pub fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, UdpConfig::default())
+	}
+
+	pub fn with_config<A: ToSocketAddrs>(address: A, config: UdpConfig) -> io::Result<Self> {
+		// resolved only once, so that the listen thread doesn't have to deal with lookup errors
+		let server_addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+		let socket = UdpSocket::bind("0.0.0.0:0")?;
+		socket.connect(&server_addresses[..])?;
+		socket.set_read_timeout(Some(UPDATE_INTERVAL))?;
+		let socket_clone = socket.try_clone().unwrap();
+		let endpoint = Arc::new(Mutex::new(ClientEndpoint::new(config, Instant::now())));
+		let endpoint_weak = Arc::downgrade(&endpoint);
+		let (sender, receiver) = std::sync::mpsc::channel();
+		std::thread::Builder::new()
+			.name("Udp Client Listen Thread".to_string())
+			.spawn(move || Self::listen_thread(socket_clone, server_addresses.into_iter().collect(), endpoint_weak, sender))
+			.unwrap();
+
+		Ok(Self {
+			socket,
+			endpoint,
+			transport_msg_receiver: receiver,
+		})
+	}
+
+	// the mtu negotiated with the server, None until the connection got accepted
+	pub fn mtu(&self) -> Option<usize> {
+		self.endpoint.lock().unwrap().mtu()
+	}
+
+	fn listen_thread(socket: UdpSocket, server_addresses: HashSet<SocketAddr>, endpoint: Weak<Mutex<ClientEndpoint>>, sender: Sender<ClientTransportEvent>) {
+		let mut buffer = [0; MAX_MSG_SIZE];
+		let mut next_update = Instant::now();
+
+		// stops once the transport got dropped
+		while let Some(endpoint) = endpoint.upgrade() {
+			let received = socket.recv_from(&mut buffer);
+			let mut endpoint = endpoint.lock().unwrap();
+			let now = Instant::now();
+
+			match received {
+				Ok((bytes_read, address)) => {
+					if server_addresses.contains(&address) {
+						endpoint.receive(&buffer[..bytes_read], now);
+					}
+				},
+				Err(e) if is_timeout(&e) => {},
+				Err(e) => {
+					match e.kind() {
+						io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+							endpoint.server_disconnected(DisconnectReason::ConnectionLost);
+						},
+						_ => {
+							if sender.send(ClientTransportEvent::FailedToReceiveMsg(e)).is_err() {
+								return;
+							}
+						},
+					}
+				}
+			}
+
+			if next_update <= now {
+				endpoint.update(now);
+				next_update = now + UPDATE_INTERVAL;
+			}
+
+			for data in endpoint.drain_outgoing() {
+				let _ = socket.send(&data);
+			}
+			while let Some(event) = endpoint.poll_event() {
+				if sender.send(event).is_err() {
+					return;
+				}
+			}
+			if endpoint.is_disconnected() {
+				return;
+			}
+		}
+	}
+}
+
+impl Drop for UdpClientTransport {
+	fn drop(&mut self) {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.disconnect();
+		for data in endpoint.drain_outgoing() {
+			let _ = self.socket.send(&data);
+		}
+	}
+}
+
+impl ClientTransport for UdpClientTransport {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		self.transport_msg_receiver.try_recv().ok()
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ClientTransportEvent> {
+		wait_on_channel(&self.transport_msg_receiver, timeout)
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.send(data, channel_id, Instant::now())?;
+		for data in endpoint.drain_outgoing() {
+			self.socket.send(&data)?;
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.flush(Instant::now());
+		for data in endpoint.drain_outgoing() {
+			self.socket.send(&data)?;
+		}
+		Ok(())
+	}
+
+	fn is_reliable(&self, channel_id: ChannelId) -> bool {
+		self.endpoint.lock().unwrap().is_reliable(channel_id)
+	}
+}
+
+pub struct UdpServerTransport {
+	transport_msg_receiver: Receiver<ServerTransportEvent>,
+	socket: UdpSocket,
+	endpoint: Arc<Mutex<ServerEndpoint>>,
+}
+
+impl UdpServerTransport {
+	pub fn bind_port(port: u16) -> io::Result<Self> {
+		Self::new(("0.0.0.0", port))
+	}
+
+	pub fn new<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+		Self::with_config(address, UdpConfig::default())
+	}
+
+	pub fn with_config<A: ToSocketAddrs>(address: A, config: UdpConfig) -> io::Result<Self> {
+		let socket = UdpSocket::bind(address)?;
+		socket.set_read_timeout(Some(UPDATE_INTERVAL))?;
+		let socket_clone = socket.try_clone().unwrap();
+		let endpoint = Arc::new(Mutex::new(ServerEndpoint::new(config)));
+		let endpoint_weak = Arc::downgrade(&endpoint);
+		let (send_channel, receive_channel) = std::sync::mpsc::channel();
+		std::thread::Builder::new()
+			.name("Udp Listen Thread".to_string())
+			.spawn(move || Self::listen_thread(socket_clone, endpoint_weak, send_channel))
+			.unwrap();
+
+		Ok(Self {
+			transport_msg_receiver: receive_channel,
+			socket,
+			endpoint,
+		})
+	}
+
+	// the mtu negotiated with the client at this address
+	pub fn mtu(&self, address: SocketAddr) -> Option<usize> {
+		self.endpoint.lock().unwrap().mtu(address)
+	}
+
+	fn listen_thread(socket: UdpSocket, endpoint: Weak<Mutex<ServerEndpoint>>, sender: Sender<ServerTransportEvent>) {
+		let mut buffer = [0; MAX_MSG_SIZE];
+		let mut next_update = Instant::now();
+
+		// stops once the transport got dropped
+		while let Some(endpoint) = endpoint.upgrade() {
+			let received = socket.recv_from(&mut buffer);
+			let mut endpoint = endpoint.lock().unwrap();
+			let now = Instant::now();
+
+			match received {
+				Ok((bytes_read, address)) => endpoint.receive(address, &buffer[..bytes_read], now),
+				Err(e) if is_timeout(&e) => {},
+				Err(e) => {
+					if sender.send(ServerTransportEvent::FailedToReceiveMsg(e)).is_err() {
+						return;
+					}
+				}
+			}
+
+			if next_update <= now {
+				endpoint.update(now);
+				next_update = now + UPDATE_INTERVAL;
+			}
+
+			for (address, data) in endpoint.drain_outgoing() {
+				let _ = socket.send_to(&data, address);
+			}
+			while let Some(event) = endpoint.poll_event() {
+				if sender.send(event).is_err() {
+					return;
+				}
+			}
+		}
+	}
+}
+
+impl ServerTransport for UdpServerTransport {
+	fn receive_event(&mut self) -> Option<ServerTransportEvent> {
+		self.transport_msg_receiver.try_recv().ok()
+	}
+
+	fn wait_event(&mut self, timeout: Option<Duration>) -> Option<ServerTransportEvent> {
+		wait_on_channel(&self.transport_msg_receiver, timeout)
+	}
+
+	fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.send(address, data, channel_id, Instant::now())?;
+		for (address, data) in endpoint.drain_outgoing() {
+			self.socket.send_to(&data, address)?;
+		}
+		Ok(())
+	}
+
+	fn send_to_many(&mut self, addresses: &[SocketAddr], data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.check_msg(data, channel_id)?;
+		let now = Instant::now();
+		for address in addresses {
+			// clients that disconnected in the meantime are skipped
+			let _ = endpoint.send(*address, data, channel_id, now);
+		}
+		let mut result = Ok(());
+		for (address, data) in endpoint.drain_outgoing() {
+			if let Err(e) = self.socket.send_to(&data, address) {
+				if result.is_ok() {
+					result = Err(SendError::Io(e));
+				}
+			}
+		}
+		result
+	}
+
+	fn flush(&mut self) -> Result<(), SendError> {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.flush(Instant::now());
+		let mut result = Ok(());
+		for (address, data) in endpoint.drain_outgoing() {
+			if let Err(e) = self.socket.send_to(&data, address) {
+				if result.is_ok() {
+					result = Err(SendError::Io(e));
+				}
+			}
+		}
+		result
+	}
+
+	fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		let mut endpoint = self.endpoint.lock().unwrap();
+		endpoint.disconnect(address, reason);
+		for (address, data) in endpoint.drain_outgoing() {
+			let _ = self.socket.send_to(&data, address);
+		}
+	}
+
+	// connection requests over the limits get rejected during the handshake
+	fn apply_config(&mut self, config: &ServerConfig) {
+		self.endpoint.lock().unwrap().apply_config(config);
+	}
+}
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::transport::{ChannelId, ConnectionRejectedReason, DisconnectReason, SendError, ServerConfig, ServerTransportEvent, TransportMsg, check_channel};
+use super::connection::Connection;
+use super::{KICK_MSG_REPETITIONS, TRANSPORT_VERSION, UdpConfig, UdpMsg, check_msg_size, serialize_udp_msg};
+
+struct PendingHandshake {
+	token: u64,
+	started: Instant,
+	// the largest mtu probe of the client we answered
+	probed_mtu: usize,
+}
+
+// Server side of the udp protocol, independent of how the socket is driven.
+// Addresses only become clients after completing the handshake.
+pub(crate) struct ServerEndpoint {
+	config: UdpConfig,
+	limits: ServerConfig,
+	connections: HashMap<SocketAddr, Connection>,
+	pending_handshakes: HashMap<SocketAddr, PendingHandshake>,
+	events: VecDeque<ServerTransportEvent>,
+	outgoing: Vec<(SocketAddr, Vec<u8>)>,
+}
+
+impl ServerEndpoint {
+	pub(crate) fn new(config: UdpConfig) -> Self {
+		Self {
+			config,
+			limits: ServerConfig::default(),
+			connections: HashMap::new(),
+			pending_handshakes: HashMap::new(),
+			events: VecDeque::new(),
+			outgoing: Vec::new(),
+		}
+	}
+
+	pub(crate) fn send(&mut self, address: SocketAddr, data: &[u8], channel_id: ChannelId, now: Instant) -> Result<(), SendError> {
+		self.check_msg(data, channel_id)?;
+
+		let connection = self.connections.get_mut(&address).ok_or(SendError::Disconnected)?;
+		for packet in connection.send(data, channel_id, now) {
+			self.outgoing.push((address, serialize_udp_msg(&UdpMsg::Packet(packet))));
+		}
+		Ok(())
+	}
+
+	pub(crate) fn flush(&mut self, now: Instant) {
+		for (address, connection) in self.connections.iter_mut() {
+			for packet in connection.flush(now) {
+				self.outgoing.push((*address, serialize_udp_msg(&UdpMsg::Packet(packet))));
+			}
+		}
+	}
+
+	pub(crate) fn apply_config(&mut self, limits: &ServerConfig) {
+		self.limits = limits.clone();
+	}
+
+	pub(crate) fn mtu(&self, address: SocketAddr) -> Option<usize> {
+		self.connections.get(&address).map(|connection| connection.mtu())
+	}
+
+	pub(crate) fn check_msg(&self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		check_channel(channel_id, self.config.channels.len())?;
+		check_msg_size(data, &self.config)
+	}
+
+	pub(crate) fn receive(&mut self, address: SocketAddr, datagram: &[u8], now: Instant) {
+		let is_connected = self.connections.contains_key(&address);
+		let udp_msg = match bincode::deserialize(datagram) {
+			Ok(udp_msg) => udp_msg,
+			Err(e) => {
+				// garbage from unknown addresses is most likely not meant for us
+				if is_connected {
+					self.events.push_back(ServerTransportEvent::FailedToReceiveMsg(io::Error::other(e)));
+				}
+				return;
+			},
+		};
+
+		match udp_msg {
+			UdpMsg::ConnectionRequest { transport_version, application_id, protocol_version } => {
+				if is_connected {
+					return;
+				}
+
+				let mut check = self.check_connection_request(transport_version, application_id, protocol_version);
+				// repeated requests of an address that already got a challenge don't count again
+				if check.is_ok() && !self.pending_handshakes.contains_key(&address) {
+					check = self.check_limits();
+				}
+				match check {
+					Ok(()) => {
+						let token = self.pending_handshakes.entry(address)
+							.or_insert_with(|| PendingHandshake {
+								token: rand::random(),
+								started: now,
+								probed_mtu: 0,
+							})
+							.token;
+						self.outgoing.push((address, serialize_udp_msg(&UdpMsg::Challenge { token })));
+					},
+					Err(reason) => self.reject(address, reason),
+				}
+			},
+			UdpMsg::ChallengeResponse { token, mtu } => {
+				if let Some(connection) = self.connections.get(&address) {
+					// our ConnectionAccepted msg got lost
+					let mtu = connection.mtu() as u32;
+					self.outgoing.push((address, serialize_udp_msg(&UdpMsg::ConnectionAccepted { mtu })));
+					return;
+				}
+
+				let token_matches = self.pending_handshakes.get(&address).is_some_and(|handshake| handshake.token == token);
+				if token_matches {
+					let handshake = self.pending_handshakes.remove(&address).unwrap();
+					// other handshakes may have completed in the meantime
+					if self.is_full() {
+						self.reject(address, ConnectionRejectedReason::ServerFull);
+						return;
+					}
+					// only probes we answered can raise the mtu above our own
+					let max_mtu = self.config.mtu.max(handshake.probed_mtu);
+					let connection = Connection::new(&self.config, (mtu as usize).min(max_mtu), now);
+					let mtu = connection.mtu() as u32;
+					self.connections.insert(address, connection);
+					self.events.push_back(ServerTransportEvent::NewClient(address));
+					self.outgoing.push((address, serialize_udp_msg(&UdpMsg::ConnectionAccepted { mtu })));
+				}
+			},
+			UdpMsg::MtuProbe { token, size, padding } => {
+				if !self.config.mtu_probing {
+					return;
+				}
+				if let Some(handshake) = self.pending_handshakes.get_mut(&address).filter(|handshake| handshake.token == token) {
+					// the size of the datagram itself proves what got through, not the claimed one
+					handshake.probed_mtu = handshake.probed_mtu.max(datagram.len().min(size as usize));
+					self.outgoing.push((address, serialize_udp_msg(&UdpMsg::MtuProbeAck { size, padding })));
+				}
+			},
+			UdpMsg::Packet(packet) => {
+				if let Some(connection) = self.connections.get_mut(&address) {
+					for (channel_id, data) in connection.receive(packet, now) {
+						self.events.push_back(ServerTransportEvent::NewMsg(TransportMsg {
+							sender_address: address,
+							channel_id,
+							data,
+						}));
+					}
+					if let Some(packet) = connection.urgent_ack(now) {
+						self.outgoing.push((address, serialize_udp_msg(&UdpMsg::Packet(packet))));
+					}
+				}
+			},
+			UdpMsg::Disconnected(reason) => {
+				if self.connections.remove(&address).is_some() {
+					self.events.push_back(ServerTransportEvent::ClientDisconnected(address, reason));
+				}
+			},
+			UdpMsg::Challenge { .. } | UdpMsg::ConnectionAccepted { .. } | UdpMsg::ConnectionRejected(_) | UdpMsg::MtuProbeAck { .. } => {},
+		}
+	}
+
+	pub(crate) fn update(&mut self, now: Instant) {
+		if let Some(handshake_timeout) = self.limits.handshake_timeout {
+			let expired_addresses: Vec<SocketAddr> = self.pending_handshakes.iter()
+				.filter(|(_address, handshake)| now.duration_since(handshake.started) >= handshake_timeout)
+				.map(|(address, _handshake)| *address)
+				.collect();
+			for address in expired_addresses {
+				self.pending_handshakes.remove(&address);
+				self.reject(address, ConnectionRejectedReason::HandshakeFailed("the handshake timed out".to_string()));
+			}
+		}
+
+		let failed_connections: Vec<(SocketAddr, DisconnectReason)> = self.connections.iter()
+			.filter_map(|(address, connection)| Some((*address, connection.failure(now)?)))
+			.collect();
+		for (address, reason) in failed_connections {
+			self.connections.remove(&address);
+			// in case the client is still alive, but only its packets got lost
+			self.outgoing.push((address, serialize_udp_msg(&UdpMsg::Disconnected(reason.clone()))));
+			self.events.push_back(ServerTransportEvent::ClientDisconnected(address, reason));
+		}
+
+		for (address, connection) in self.connections.iter_mut() {
+			for packet in connection.update(now) {
+				self.outgoing.push((*address, serialize_udp_msg(&UdpMsg::Packet(packet))));
+			}
+		}
+	}
+
+	pub(crate) fn disconnect(&mut self, address: SocketAddr, reason: DisconnectReason) {
+		self.pending_handshakes.remove(&address);
+		if let Some(mut connection) = self.connections.remove(&address) {
+			// queued msgs still go out before the kick
+			for packet in connection.flush(Instant::now()) {
+				self.outgoing.push((address, serialize_udp_msg(&UdpMsg::Packet(packet))));
+			}
+			let data = serialize_udp_msg(&UdpMsg::Disconnected(reason));
+			for _ in 0..KICK_MSG_REPETITIONS {
+				self.outgoing.push((address, data.clone()));
+			}
+		}
+	}
+
+	pub(crate) fn poll_event(&mut self) -> Option<ServerTransportEvent> {
+		self.events.pop_front()
+	}
+
+	pub(crate) fn drain_outgoing(&mut self) -> impl Iterator<Item = (SocketAddr, Vec<u8>)> + '_ {
+		self.outgoing.drain(..)
+	}
+
+	fn is_full(&self) -> bool {
+		self.limits.max_clients.is_some_and(|max_clients| self.connections.len() >= max_clients)
+	}
+
+	// checked before a handshake gets started, so that spoofed requests can't allocate unlimited state
+	fn check_limits(&self) -> Result<(), ConnectionRejectedReason> {
+		if self.is_full() {
+			Err(ConnectionRejectedReason::ServerFull)
+		}
+		else if self.limits.max_pending_handshakes.is_some_and(|max_pending| self.pending_handshakes.len() >= max_pending) {
+			Err(ConnectionRejectedReason::TooManyPendingHandshakes)
+		}
+		else {
+			Ok(())
+		}
+	}
+
+	fn reject(&mut self, address: SocketAddr, reason: ConnectionRejectedReason) {
+		self.outgoing.push((address, serialize_udp_msg(&UdpMsg::ConnectionRejected(reason.clone()))));
+		self.events.push_back(ServerTransportEvent::ClientRejected(address, reason));
+	}
+
+	fn check_connection_request(&self, transport_version: u32, application_id: u64, protocol_version: u32) -> Result<(), ConnectionRejectedReason> {
+		if transport_version != TRANSPORT_VERSION {
+			Err(ConnectionRejectedReason::TransportVersionMismatch)
+		}
+		else if application_id != self.config.application_id {
+			Err(ConnectionRejectedReason::ApplicationIdMismatch)
+		}
+		else if protocol_version != self.config.protocol_version {
+			Err(ConnectionRejectedReason::ProtocolVersionMismatch {
+				server_version: self.config.protocol_version,
+				client_version: protocol_version,
+			})
+		}
+		else {
+			Ok(())
+		}
+	}
+}
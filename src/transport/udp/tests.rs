@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::transport::{DEFAULT_HANDSHAKE_TIMEOUT, ClientTransportEvent, ConnectionRejectedReason, ServerTransportEvent};
+use super::*;
+
+// drives both endpoints without sockets, every datagram arrives unless a test drops it on purpose
+struct EndpointPair {
+	client: ClientEndpoint,
+	server: ServerEndpoint,
+	client_address: SocketAddr,
+	now: Instant,
+	// larger datagrams get dropped, like on a path with a smaller mtu
+	max_datagram_size: usize,
+}
+
+impl EndpointPair {
+	fn new(client_config: UdpConfig, server_config: UdpConfig) -> Self {
+		let now = Instant::now();
+		Self {
+			client: ClientEndpoint::new(client_config, now),
+			server: ServerEndpoint::new(server_config),
+			client_address: SocketAddr::from(([127, 0, 0, 1], 4000)),
+			now,
+			max_datagram_size: usize::MAX,
+		}
+	}
+
+	// delivers datagrams in both directions until neither side has anything left to send
+	fn exchange(&mut self) {
+		while self.deliver_to_server() + self.deliver_to_client() > 0 {}
+	}
+
+	// returns how many datagrams were sent, including the dropped ones
+	fn deliver_to_server(&mut self) -> usize {
+		let datagrams: Vec<Vec<u8>> = self.client.drain_outgoing().collect();
+		for datagram in datagrams.iter().filter(|datagram| datagram.len() <= self.max_datagram_size) {
+			self.server.receive(self.client_address, datagram, self.now);
+		}
+		datagrams.len()
+	}
+
+	fn deliver_to_client(&mut self) -> usize {
+		let datagrams: Vec<Vec<u8>> = self.server.drain_outgoing().map(|(_address, datagram)| datagram).collect();
+		for datagram in datagrams.iter().filter(|datagram| datagram.len() <= self.max_datagram_size) {
+			self.client.receive(datagram, self.now);
+		}
+		datagrams.len()
+	}
+
+	fn advance(&mut self, duration: Duration) {
+		self.now += duration;
+		self.client.update(self.now);
+		self.server.update(self.now);
+	}
+
+	fn drop_outgoing(&mut self) {
+		self.client.drain_outgoing().for_each(drop);
+		self.server.drain_outgoing().for_each(drop);
+	}
+
+	fn client_events(&mut self) -> Vec<ClientTransportEvent> {
+		std::iter::from_fn(|| self.client.poll_event()).collect()
+	}
+
+	fn server_events(&mut self) -> Vec<ServerTransportEvent> {
+		std::iter::from_fn(|| self.server.poll_event()).collect()
+	}
+}
+
+fn connected_pair(config: UdpConfig) -> EndpointPair {
+	let mut pair = EndpointPair::new(config.clone(), config);
+	pair.exchange();
+	assert!(matches!(pair.server_events()[..], [ServerTransportEvent::NewClient(_)]));
+	pair
+}
+
+#[test]
+fn handshake_admits_the_client() {
+	let mut pair = EndpointPair::new(UdpConfig::default(), UdpConfig::default());
+	pair.client.send(b"early", 0, pair.now).unwrap();
+	assert_eq!(pair.client.mtu(), None);
+
+	pair.exchange();
+	let client_address = pair.client_address;
+	let events = pair.server_events();
+	assert!(matches!(events[0], ServerTransportEvent::NewClient(address) if address == client_address));
+	// msgs sent while connecting are held back until the connection got accepted
+	assert!(matches!(&events[1], ServerTransportEvent::NewMsg(msg) if msg.data == b"early"));
+	assert_eq!(pair.client.mtu(), Some(UdpConfig::default().mtu));
+}
+
+#[test]
+fn mismatched_versions_get_rejected() {
+	let client_config = UdpConfig {
+		protocol_version: 2,
+		..UdpConfig::default()
+	};
+	let mut pair = EndpointPair::new(client_config, UdpConfig::default());
+	pair.exchange();
+
+	let expected_reason = ConnectionRejectedReason::ProtocolVersionMismatch {
+		server_version: 0,
+		client_version: 2,
+	};
+	assert!(matches!(&pair.client_events()[..], [ClientTransportEvent::ConnectionRejected(reason)] if *reason == expected_reason));
+	assert!(matches!(pair.server_events()[..], [ServerTransportEvent::ClientRejected(..)]));
+	assert!(pair.client.is_disconnected());
+}
+
+#[test]
+fn mismatched_application_ids_get_rejected() {
+	let client_config = UdpConfig {
+		application_id: 7,
+		..UdpConfig::default()
+	};
+	let mut pair = EndpointPair::new(client_config, UdpConfig::default());
+	pair.exchange();
+	assert!(matches!(pair.client_events()[..], [ClientTransportEvent::ConnectionRejected(ConnectionRejectedReason::ApplicationIdMismatch)]));
+}
+
+#[test]
+fn challenge_responses_need_the_token() {
+	let mut server = ServerEndpoint::new(UdpConfig::default());
+	let address = SocketAddr::from(([127, 0, 0, 1], 4000));
+	let now = Instant::now();
+	let request = serialize_udp_msg(&UdpMsg::ConnectionRequest {
+		transport_version: TRANSPORT_VERSION,
+		application_id: 0,
+		protocol_version: 0,
+	});
+	server.receive(address, &request, now);
+	let (_address, challenge) = server.drain_outgoing().next().unwrap();
+	let Ok(UdpMsg::Challenge { token }) = bincode::deserialize(&challenge) else { panic!("expected a challenge") };
+
+	// neither a guessed token nor the right one from another address gets a spoofed client in
+	let spoofed_address = SocketAddr::from(([127, 0, 0, 1], 4001));
+	server.receive(address, &serialize_udp_msg(&UdpMsg::ChallengeResponse { token: token.wrapping_add(1), mtu: 1200 }), now);
+	server.receive(spoofed_address, &serialize_udp_msg(&UdpMsg::ChallengeResponse { token, mtu: 1200 }), now);
+	assert!(server.poll_event().is_none());
+
+	server.receive(address, &serialize_udp_msg(&UdpMsg::ChallengeResponse { token, mtu: 1200 }), now);
+	assert!(matches!(server.poll_event(), Some(ServerTransportEvent::NewClient(client_address)) if client_address == address));
+}
+
+#[test]
+fn stale_handshakes_get_rejected() {
+	let mut server = ServerEndpoint::new(UdpConfig::default());
+	let address = SocketAddr::from(([127, 0, 0, 1], 4000));
+	let now = Instant::now();
+	let request = serialize_udp_msg(&UdpMsg::ConnectionRequest {
+		transport_version: TRANSPORT_VERSION,
+		application_id: 0,
+		protocol_version: 0,
+	});
+	server.receive(address, &request, now);
+	server.drain_outgoing().for_each(drop);
+
+	// the client never answers the challenge
+	server.update(now + DEFAULT_HANDSHAKE_TIMEOUT);
+	assert!(matches!(server.poll_event(), Some(ServerTransportEvent::ClientRejected(rejected_address, ConnectionRejectedReason::HandshakeFailed(_))) if rejected_address == address));
+	let (_address, rejection) = server.drain_outgoing().next().unwrap();
+	assert!(matches!(bincode::deserialize(&rejection), Ok(UdpMsg::ConnectionRejected(ConnectionRejectedReason::HandshakeFailed(_)))));
+}
+
+#[test]
+fn lost_handshake_msgs_get_resent() {
+	let mut pair = EndpointPair::new(UdpConfig::default(), UdpConfig::default());
+	pair.drop_outgoing();
+	pair.advance(HANDSHAKE_RESEND_INTERVAL);
+	pair.exchange();
+	assert!(matches!(pair.server_events()[..], [ServerTransportEvent::NewClient(_)]));
+	assert!(pair.client.mtu().is_some());
+}
+
+#[test]
+fn unanswered_handshakes_time_out() {
+	let mut pair = EndpointPair::new(UdpConfig::default(), UdpConfig::default());
+	pair.drop_outgoing();
+	pair.advance(UdpConfig::default().idle_timeout);
+	assert!(matches!(pair.client_events()[..], [ClientTransportEvent::ServerDisconnected(DisconnectReason::TimedOut)]));
+}
+
+#[test]
+fn heartbeats_keep_idle_connections_alive() {
+	let config = UdpConfig::default();
+	let mut pair = connected_pair(config.clone());
+	for _ in 0..3 {
+		pair.advance(config.idle_timeout / 2);
+		pair.exchange();
+	}
+	assert!(pair.client_events().is_empty());
+	assert!(pair.server_events().is_empty());
+	assert!(pair.client.mtu().is_some());
+}
+
+#[test]
+fn silent_peers_time_out() {
+	let config = UdpConfig::default();
+	let mut pair = connected_pair(config.clone());
+	pair.advance(config.idle_timeout / 2);
+	pair.drop_outgoing();
+	pair.advance(config.idle_timeout / 2);
+
+	let client_address = pair.client_address;
+	assert!(matches!(pair.server_events()[..], [ServerTransportEvent::ClientDisconnected(address, DisconnectReason::TimedOut)] if address == client_address));
+	assert!(matches!(pair.client_events()[..], [ClientTransportEvent::ServerDisconnected(DisconnectReason::TimedOut)]));
+	assert!(matches!(pair.server.send(client_address, b"msg", 0, pair.now), Err(SendError::Disconnected)));
+}
+
+#[test]
+fn connections_use_the_smaller_mtu() {
+	let client_config = UdpConfig {
+		mtu: 1000,
+		..UdpConfig::default()
+	};
+	let mut pair = EndpointPair::new(client_config, UdpConfig::default());
+	pair.exchange();
+	assert_eq!(pair.client.mtu(), Some(1000));
+	assert_eq!(pair.server.mtu(pair.client_address), Some(1000));
+}
+
+#[test]
+fn mtu_probing_raises_the_mtu_to_the_largest_answered_probe() {
+	let config = UdpConfig {
+		mtu_probing: true,
+		..UdpConfig::default()
+	};
+	let mut pair = EndpointPair::new(config.clone(), config);
+	pair.max_datagram_size = 1500;
+	pair.exchange();
+	// the largest probe never gets answered, so the handshake waits for the probe timeout
+	assert_eq!(pair.client.mtu(), None);
+
+	pair.advance(MTU_PROBE_TIMEOUT);
+	pair.exchange();
+	assert_eq!(pair.client.mtu(), Some(1472));
+	assert_eq!(pair.server.mtu(pair.client_address), Some(1472));
+}
+
+#[test]
+fn mtu_probing_needs_both_sides() {
+	let client_config = UdpConfig {
+		mtu_probing: true,
+		..UdpConfig::default()
+	};
+	let mut pair = EndpointPair::new(client_config, UdpConfig::default());
+	pair.exchange();
+	pair.advance(MTU_PROBE_TIMEOUT);
+	pair.exchange();
+	assert_eq!(pair.client.mtu(), Some(UdpConfig::default().mtu));
+	assert_eq!(pair.server.mtu(pair.client_address), Some(UdpConfig::default().mtu));
+}
+
+#[test]
+fn unresolvable_server_addresses_fail_the_constructor() {
+	assert!(UdpClientTransport::new("missing port").is_err());
+}
+
+#[test]
+fn sending_on_unconfigured_channels_fails() {
+	let mut pair = connected_pair(UdpConfig::default());
+	let client_address = pair.client_address;
+	assert!(matches!(pair.client.send(b"msg", 1, pair.now), Err(SendError::InvalidChannel { channel_id: 1, channel_count: 1 })));
+	assert!(matches!(pair.server.send(client_address, b"msg", 1, pair.now), Err(SendError::InvalidChannel { channel_id: 1, channel_count: 1 })));
+	assert!(pair.client.send(b"msg", 0, pair.now).is_ok());
+}
+
+#[test]
+fn clients_wait_for_the_mtu_of_the_server() {
+	let mut pair = EndpointPair::new(UdpConfig::default(), UdpConfig::default());
+	pair.deliver_to_server();
+	pair.deliver_to_client();
+	pair.deliver_to_server();
+	// the ConnectionAccepted msg gets lost, but the first packet of the server arrives
+	pair.server.drain_outgoing().for_each(drop);
+	pair.server.send(pair.client_address, b"msg", 0, pair.now).unwrap();
+	pair.deliver_to_client();
+	assert_eq!(pair.client.mtu(), None);
+	assert!(pair.client_events().is_empty());
+
+	// the server answers the resent ChallengeResponse with its mtu again, the msg gets resent as well
+	for _ in 0..100 {
+		pair.advance(UPDATE_INTERVAL);
+		pair.exchange();
+	}
+	assert_eq!(pair.client.mtu(), pair.server.mtu(pair.client_address));
+	assert!(matches!(&pair.client_events()[..], [ClientTransportEvent::NewMsg(0, data)] if data == b"msg"));
+}
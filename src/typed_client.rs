@@ -0,0 +1,67 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+use serde::{de::DeserializeOwned, Serialize};
+use crate::client::{Client, ClientEvent};
+use crate::codec::{BincodeCodec, Codec};
+use crate::rpc::RequestId;
+use crate::transport::{ChannelId, ClientTransport, SendError};
+
+// Client with its msg types fixed once, so that sending or expecting the wrong type doesn't compile.
+// In is what the server sends, Out what the client sends to it.
+pub struct TypedClient<In, Out, C: Codec = BincodeCodec> {
+	client: Client<C>,
+	msgs: PhantomData<fn(Out) -> In>,
+}
+
+impl<In: DeserializeOwned, Out: Serialize> TypedClient<In, Out> {
+	pub fn new(transport: Box<dyn ClientTransport>) -> Self {
+		Self::from(Client::new(transport))
+	}
+}
+
+impl<In: DeserializeOwned, Out: Serialize, C: Codec> TypedClient<In, Out, C> {
+	pub fn with_codec(transport: Box<dyn ClientTransport>, codec: C) -> Self {
+		Self::from(Client::with_codec(transport, codec))
+	}
+
+	pub fn with_connect_payload(transport: Box<dyn ClientTransport>, codec: C, connect_payload: impl Into<Vec<u8>>) -> Result<Self, SendError> {
+		Client::with_connect_payload(transport, codec, connect_payload).map(Self::from)
+	}
+
+	pub fn handle_event(&mut self) -> Option<ClientEvent<In>> {
+		self.client.handle_event()
+	}
+
+	pub fn wait_event(&mut self, timeout: Duration) -> Option<ClientEvent<In>> {
+		self.client.wait_event(timeout)
+	}
+
+	pub fn events(&mut self) -> impl Iterator<Item = ClientEvent<In>> + '_ {
+		self.client.events()
+	}
+
+	pub fn send(&mut self, msg: &Out, channel_id: ChannelId) -> Result<(), SendError> {
+		self.client.send(msg, channel_id)
+	}
+
+	pub fn request(&mut self, msg: &Out, channel_id: ChannelId, timeout: Duration) -> Result<RequestId, SendError> {
+		self.client.request(msg, channel_id, timeout)
+	}
+
+	pub fn flush(&mut self) -> Result<(), SendError> {
+		self.client.flush()
+	}
+
+	pub fn into_inner(self) -> Client<C> {
+		self.client
+	}
+}
+
+impl<In, Out, C: Codec> From<Client<C>> for TypedClient<In, Out, C> {
+	fn from(client: Client<C>) -> Self {
+		Self {
+			client,
+			msgs: PhantomData,
+		}
+	}
+}
@@ -0,0 +1,103 @@
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::time::Duration;
+use serde::{de::DeserializeOwned, Serialize};
+use crate::auth::Authenticator;
+use crate::codec::{BincodeCodec, Codec};
+use crate::rpc::ClientRequestId;
+use crate::server::{ClientId, Server, ServerEvent};
+use crate::transport::{ChannelId, SendError, ServerConfig, ServerTransport};
+
+// Server with its msg types fixed once, so that sending or expecting the wrong type doesn't compile.
+// In is what the clients send, Out what the server sends to them.
+pub struct TypedServer<In, Out, C: Codec = BincodeCodec> {
+	server: Server<C>,
+	msgs: PhantomData<fn(Out) -> In>,
+}
+
+impl<In: DeserializeOwned, Out: Serialize> TypedServer<In, Out> {
+	pub fn new(transport: Box<dyn ServerTransport>) -> Self {
+		Self::from(Server::new(transport))
+	}
+}
+
+impl<In: DeserializeOwned, Out: Serialize, C: Codec> TypedServer<In, Out, C> {
+	pub fn with_codec(transport: Box<dyn ServerTransport>, codec: C) -> Self {
+		Self::from(Server::with_codec(transport, codec))
+	}
+
+	pub fn with_config(transport: Box<dyn ServerTransport>, codec: C, config: ServerConfig) -> Self {
+		Self::from(Server::with_config(transport, codec, config))
+	}
+
+	pub fn with_authenticator(self, authenticator: impl Authenticator + 'static) -> Self {
+		Self::from(self.server.with_authenticator(authenticator))
+	}
+
+	pub fn client_address(&self, client_id: ClientId) -> Option<SocketAddr> {
+		self.server.client_address(client_id)
+	}
+
+	pub fn client_identity(&self, client_id: ClientId) -> Option<&str> {
+		self.server.client_identity(client_id)
+	}
+
+	// all connected clients, ordered by when they connected
+	pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+		self.server.clients()
+	}
+
+	pub fn send_to(&mut self, client_id: ClientId, msg: &Out, channel_id: ChannelId) -> Result<(), SendError> {
+		self.server.send_to(client_id, msg, channel_id)
+	}
+
+	// unknown client ids are skipped
+	pub fn send_to_many(&mut self, client_ids: impl IntoIterator<Item = ClientId>, msg: &Out, channel_id: ChannelId) -> Result<(), SendError> {
+		self.server.send_to_many(client_ids, msg, channel_id)
+	}
+
+	pub fn broadcast(&mut self, msg: &Out, channel_id: ChannelId) -> Result<(), SendError> {
+		self.server.broadcast(msg, channel_id)
+	}
+
+	pub fn broadcast_except(&mut self, except_client_id: ClientId, msg: &Out, channel_id: ChannelId) -> Result<(), SendError> {
+		self.server.broadcast_except(except_client_id, msg, channel_id)
+	}
+
+	pub fn respond(&mut self, request_id: ClientRequestId, msg: &Out) -> Result<(), SendError> {
+		self.server.respond(request_id, msg)
+	}
+
+	pub fn flush(&mut self) -> Result<(), SendError> {
+		self.server.flush()
+	}
+
+	pub fn disconnect(&mut self, client_id: ClientId, reason: impl Into<String>) {
+		self.server.disconnect(client_id, reason);
+	}
+
+	pub fn receive_event(&mut self) -> Option<ServerEvent<In>> {
+		self.server.receive_event()
+	}
+
+	pub fn wait_event(&mut self, timeout: Duration) -> Option<ServerEvent<In>> {
+		self.server.wait_event(timeout)
+	}
+
+	pub fn events(&mut self) -> impl Iterator<Item = ServerEvent<In>> + '_ {
+		self.server.events()
+	}
+
+	pub fn into_inner(self) -> Server<C> {
+		self.server
+	}
+}
+
+impl<In, Out, C: Codec> From<Server<C>> for TypedServer<In, Out, C> {
+	fn from(server: Server<C>) -> Self {
+		Self {
+			server,
+			msgs: PhantomData,
+		}
+	}
+}
@@ -0,0 +1,30 @@
+#![cfg(feature = "tokio")]
+use std::time::Duration;
+use client_server::transport::asynchronous::tcp::{AsyncTcpClientTransport, AsyncTcpServerTransport};
+use client_server::{AsyncClient, AsyncServer, BincodeCodec, ClientEvent, DisconnectReason, ServerConfig, ServerEvent};
+
+#[tokio::test]
+async fn rate_limited_clients_get_notified() {
+	let config = ServerConfig {
+		max_msgs_per_second: Some(2),
+		..ServerConfig::default()
+	};
+	let server_transport = AsyncTcpServerTransport::new("127.0.0.1:0").await.unwrap();
+	let server_address = server_transport.local_address();
+	let mut server = AsyncServer::with_config(Box::new(server_transport), BincodeCodec, config);
+	let mut client = AsyncClient::new(Box::new(AsyncTcpClientTransport::new(server_address).await.unwrap()));
+	for i in 0..3 {
+		client.send(&i, 0).await.unwrap();
+	}
+
+	let Some(ServerEvent::<u32>::NewClient(client_id)) = server.next_event().await else { panic!("expected a new client") };
+	for _ in 0..2 {
+		let Some(ServerEvent::<u32>::NewMsg(_)) = server.next_event().await else { panic!("expected a msg") };
+	}
+	let Some(ServerEvent::<u32>::ClientDisconnected(id, DisconnectReason::RateLimited)) = server.next_event().await else { panic!("expected a kick") };
+	assert_eq!(id, client_id);
+
+	// the server isn't polled anymore, the kick has to go out anyway
+	let event = tokio::time::timeout(Duration::from_secs(5), client.next_event::<u32>()).await.expect("the client never got kicked");
+	let Some(ClientEvent::ServerDisconnected(DisconnectReason::RateLimited)) = event else { panic!("expected a kick") };
+}
@@ -0,0 +1,175 @@
+#![cfg(feature = "encryption")]
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use client_server::transport::encryption::datagram::{EncryptedClientTransport, EncryptedServerTransport, ServerKey};
+use client_server::transport::encryption::tls::{self, TlsClientTransport, TlsServerTransport};
+use client_server::transport::memory::{MemoryClientTransport, MemoryServerTransport};
+use client_server::transport::{ChannelId, ClientTransport, ClientTransportEvent, ConnectionRejectedReason, SendError, ServerTransport};
+use client_server::{BincodeCodec, Client, ClientEvent, DisconnectReason, Server, ServerConfig, ServerEvent};
+
+// sends everything twice, like an attacker replaying packets
+struct Replaying(MemoryClientTransport);
+
+impl ClientTransport for Replaying {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		self.0.receive_event()
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		self.0.send(data, channel_id)?;
+		self.0.send(data, channel_id)
+	}
+}
+
+// drives both sides until the server received a msg, the client sent one before the handshake was done
+fn exchange_msgs(server_transport: impl ServerTransport + 'static, client_transport: impl ClientTransport + 'static) {
+	let mut server = Server::new(Box::new(server_transport));
+	let mut client = Client::new(Box::new(client_transport));
+	client.send(&"hello".to_string(), 0).unwrap();
+
+	let mut received = Vec::new();
+	let mut client_id = None;
+	for _ in 0..10 {
+		while let Some(event) = server.receive_event::<String>() {
+			match event {
+				ServerEvent::NewClient(id) => {
+					client_id = Some(id);
+					server.send_to(id, &"welcome".to_string(), 0).unwrap();
+				},
+				ServerEvent::NewMsg(msg) => received.push(msg.msg),
+				_ => panic!("unexpected server event"),
+			}
+		}
+		while let Some(event) = client.handle_event::<String>() {
+			let ClientEvent::MsgFromServer(msg) = event else { panic!("unexpected client event") };
+			received.push(msg.msg);
+		}
+	}
+	assert!(client_id.is_some());
+	received.sort();
+	assert_eq!(received, ["hello", "welcome"]);
+}
+
+#[test]
+fn tls_session() {
+	let cert = tls::self_signed_cert(vec!["localhost".to_string()]).unwrap();
+	let server_config = tls::server_config(vec![cert.cert.clone()], cert.key).unwrap();
+	let client_config = tls::client_config([cert.cert]).unwrap();
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+
+	let client_transport = TlsClientTransport::new(connector.connect(), client_config, "localhost").unwrap();
+	exchange_msgs(TlsServerTransport::new(transport, server_config), client_transport);
+}
+
+#[test]
+fn tls_rejects_untrusted_server() {
+	let cert = tls::self_signed_cert(vec!["localhost".to_string()]).unwrap();
+	let other_cert = tls::self_signed_cert(vec!["localhost".to_string()]).unwrap();
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(TlsServerTransport::new(transport, tls::server_config(vec![cert.cert], cert.key).unwrap())));
+	let mut client = Client::new(Box::new(TlsClientTransport::new(connector.connect(), tls::client_config([other_cert.cert]).unwrap(), "localhost").unwrap()));
+
+	assert!(server.receive_event::<String>().is_none());
+	let Some(ClientEvent::<String>::ConnectionRejected(_)) = client.handle_event() else { panic!("expected a rejection") };
+	assert!(client.send(&"hello".to_string(), 0).is_err());
+}
+
+#[test]
+fn datagram_session_drops_replays() {
+	let key = ServerKey::generate();
+	let public_key = key.public_key();
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+
+	let client_transport = EncryptedClientTransport::new(Replaying(connector.connect()), public_key);
+	exchange_msgs(EncryptedServerTransport::new(transport, key), client_transport);
+}
+
+#[test]
+fn tls_rejects_clients_that_never_finish_the_handshake() {
+	let cert = tls::self_signed_cert(vec!["localhost".to_string()]).unwrap();
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let config = ServerConfig {
+		handshake_timeout: Some(Duration::from_millis(50)),
+		..ServerConfig::default()
+	};
+	let mut server = Server::with_config(Box::new(TlsServerTransport::new(transport, tls::server_config(vec![cert.cert], cert.key).unwrap())), BincodeCodec, config);
+	// never starts the tls handshake
+	let mut client = Client::new(Box::new(connector.connect()));
+
+	assert!(server.receive_event::<String>().is_none());
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::HandshakeFailed(_))) = server.wait_event(Duration::from_secs(1)) else { panic!("expected a rejection") };
+	let Some(ClientEvent::<String>::ConnectionRejected(ConnectionRejectedReason::HandshakeFailed(_))) = client.handle_event() else { panic!("expected a rejection") };
+}
+
+// lets a new client reuse the connection of the wrapped transport, like a client that restarts on the same address
+struct Shared(Rc<RefCell<MemoryClientTransport>>);
+
+impl ClientTransport for Shared {
+	fn receive_event(&mut self) -> Option<ClientTransportEvent> {
+		self.0.borrow_mut().receive_event()
+	}
+
+	fn send(&mut self, data: &[u8], channel_id: ChannelId) -> Result<(), SendError> {
+		self.0.borrow_mut().send(data, channel_id)
+	}
+}
+
+// drives both sides until the server got the msg of the client
+fn send_until_received(server: &mut Server, client: &mut Client, msg: &str) -> Vec<ServerEvent<String>> {
+	client.send(&msg.to_string(), 0).unwrap();
+	let mut events = Vec::new();
+	for _ in 0..10 {
+		while let Some(event) = server.receive_event::<String>() {
+			let received = matches!(&event, ServerEvent::NewMsg(received) if received.msg == msg);
+			events.push(event);
+			if received {
+				return events;
+			}
+		}
+		while client.handle_event::<String>().is_some() {}
+	}
+	panic!("the msg never arrived");
+}
+
+#[test]
+fn datagram_session_gets_replaced_by_a_new_handshake() {
+	let key = ServerKey::generate();
+	let public_key = key.public_key();
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(EncryptedServerTransport::new(transport, key)));
+	let connection = Rc::new(RefCell::new(connector.connect()));
+
+	let mut client = Client::new(Box::new(EncryptedClientTransport::new(Shared(connection.clone()), public_key)));
+	let events = send_until_received(&mut server, &mut client, "hello");
+	let [ServerEvent::NewClient(old_id), ServerEvent::NewMsg(_)] = events[..] else { panic!("expected a new client") };
+
+	let mut restarted_client = Client::new(Box::new(EncryptedClientTransport::new(Shared(connection), public_key)));
+	let events = send_until_received(&mut server, &mut restarted_client, "hello again");
+	let [ServerEvent::ClientDisconnected(id, DisconnectReason::ConnectionLost), ServerEvent::NewClient(new_id), ServerEvent::NewMsg(_)] = events[..] else { panic!("expected the old client to be replaced") };
+	assert_eq!(id, old_id);
+	assert_ne!(new_id, old_id);
+}
+
+#[test]
+fn datagram_rejects_clients_that_never_say_hello() {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let config = ServerConfig {
+		max_pending_handshakes: Some(1),
+		handshake_timeout: Some(Duration::from_millis(50)),
+		..ServerConfig::default()
+	};
+	let mut server = Server::with_config(Box::new(EncryptedServerTransport::new(transport, ServerKey::generate())), BincodeCodec, config);
+	// neither of them starts the handshake
+	let _pending_client = Client::new(Box::new(connector.connect()));
+	let _rejected_client = Client::new(Box::new(connector.connect()));
+
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::TooManyPendingHandshakes)) = server.receive_event() else { panic!("expected a rejection") };
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::HandshakeFailed(_))) = server.wait_event(Duration::from_secs(1)) else { panic!("expected a timeout") };
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use client_server::transport::SendError;
+use client_server::transport::memory::{MemoryConnector, MemoryServerTransport};
+use client_server::transport::udp::{UdpClientTransport, UdpConfig};
+use client_server::{BincodeCodec, Client, ClientEvent, ConnectionRejectedReason, DeliveryMode, DisconnectReason, Server, ServerConfig, ServerEvent};
+
+fn server_with_config(config: ServerConfig) -> (Server, MemoryConnector) {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	(Server::with_config(Box::new(transport), BincodeCodec, config), connector)
+}
+
+#[test]
+fn full_server_rejects_clients() {
+	let (mut server, connector) = server_with_config(ServerConfig {
+		max_clients: Some(1),
+		..ServerConfig::default()
+	});
+	let _client = Client::new(Box::new(connector.connect()));
+	let mut rejected_client = Client::new(Box::new(connector.connect()));
+
+	let Some(ServerEvent::<String>::NewClient(_)) = server.receive_event() else { panic!("expected a new client") };
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::ServerFull)) = server.receive_event() else { panic!("expected a rejection") };
+	let Some(ClientEvent::<String>::ConnectionRejected(ConnectionRejectedReason::ServerFull)) = rejected_client.handle_event() else { panic!("expected a rejection") };
+}
+
+#[test]
+fn flooding_clients_get_kicked() {
+	let (mut server, connector) = server_with_config(ServerConfig {
+		max_msgs_per_second: Some(3),
+		..ServerConfig::default()
+	});
+	let mut client = Client::new(Box::new(connector.connect()));
+	for i in 0..4 {
+		client.send(&i, 0).unwrap();
+	}
+
+	let Some(ServerEvent::<u32>::NewClient(client_id)) = server.receive_event() else { panic!("expected a new client") };
+	for _ in 0..3 {
+		let Some(ServerEvent::<u32>::NewMsg(_)) = server.receive_event() else { panic!("expected a msg") };
+	}
+	let Some(ServerEvent::<u32>::ClientDisconnected(id, DisconnectReason::RateLimited)) = server.receive_event() else { panic!("expected a kick") };
+	assert_eq!(id, client_id);
+	assert_eq!(server.clients().count(), 0);
+	let Some(ClientEvent::<u32>::ServerDisconnected(DisconnectReason::RateLimited)) = client.handle_event() else { panic!("expected a kick") };
+}
+
+#[test]
+fn oversized_msgs_get_kicked() {
+	let (mut server, connector) = server_with_config(ServerConfig {
+		max_msg_size: Some(16),
+		..ServerConfig::default()
+	});
+	let mut client = Client::new(Box::new(connector.connect()));
+	client.send(&"short".to_string(), 0).unwrap();
+	client.send(&"way over the limit of the server".to_string(), 0).unwrap();
+
+	let Some(ServerEvent::<String>::NewClient(_)) = server.receive_event() else { panic!("expected a new client") };
+	let Some(ServerEvent::<String>::NewMsg(_)) = server.receive_event() else { panic!("expected a msg") };
+	let Some(ServerEvent::<String>::ClientDisconnected(_, DisconnectReason::MsgTooLarge { max_size: 16, .. })) = server.receive_event() else { panic!("expected a kick") };
+}
+
+#[test]
+fn pending_handshakes_are_limited() {
+	let (server, connector) = server_with_config(ServerConfig {
+		max_pending_handshakes: Some(1),
+		handshake_timeout: Some(Duration::from_millis(50)),
+		..ServerConfig::default()
+	});
+	let mut server = server.with_authenticator(|_, _: &[u8]| Ok("anyone".to_string()));
+	let mut pending_client = Client::new(Box::new(connector.connect()));
+	let _rejected_client = Client::new(Box::new(connector.connect()));
+
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::TooManyPendingHandshakes)) = server.receive_event() else { panic!("expected a rejection") };
+	assert!(server.receive_event::<String>().is_none());
+
+	// the client that never sends its connect payload frees its slot again
+	let Some(ServerEvent::<String>::ClientRejected(_, ConnectionRejectedReason::Rejected(_))) = server.wait_event(Duration::from_secs(1)) else { panic!("expected a timeout") };
+	let Some(ClientEvent::<String>::ConnectionRejected(ConnectionRejectedReason::Rejected(_))) = pending_client.handle_event() else { panic!("expected a rejection") };
+	let _admitted_client = Client::with_connect_payload(Box::new(connector.connect()), BincodeCodec, b"hello".to_vec()).unwrap();
+	let Some(ServerEvent::<String>::NewClient(_)) = server.receive_event() else { panic!("expected a new client") };
+}
+
+#[test]
+fn connect_payload_needs_a_reliable_channel() {
+	let config = UdpConfig {
+		channels: vec![DeliveryMode::Unreliable, DeliveryMode::ReliableOrdered],
+		..Default::default()
+	};
+	let transport = UdpClientTransport::with_config(("127.0.0.1", 1), config).unwrap();
+	assert!(matches!(Client::with_connect_payload(Box::new(transport), BincodeCodec, "token"), Err(SendError::UnreliableChannel(0))));
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+use client_server::transport::memory::MemoryServerTransport;
+use client_server::transport::{ConnectionRejectedReason, DisconnectReason};
+use client_server::{BincodeCodec, Client, ClientEvent, Server, ServerEvent};
+
+#[test]
+fn session() {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(transport));
+	let mut client = Client::new(Box::new(connector.connect()));
+
+	let Some(ServerEvent::<String>::NewClient(client_id)) = server.receive_event() else { panic!("expected a new client") };
+	client.send(&"ping".to_string(), 0).unwrap();
+	let Some(ServerEvent::NewMsg(msg)) = server.receive_event::<String>() else { panic!("expected a msg") };
+	assert_eq!((msg.client_id, msg.msg.as_str()), (client_id, "ping"));
+
+	let request_id = client.request(&"question".to_string(), 1, Duration::from_secs(60)).unwrap();
+	let Some(ServerEvent::Request { request_id: client_request_id, msg, .. }) = server.receive_event::<String>() else { panic!("expected a request") };
+	assert_eq!(msg, "question");
+	server.respond(client_request_id, &"answer".to_string()).unwrap();
+	let Some(ClientEvent::Response(id, msg)) = client.handle_event::<String>() else { panic!("expected a response") };
+	assert_eq!((id, msg.as_str()), (request_id, "answer"));
+
+	server.disconnect(client_id, "bye");
+	assert!(server.receive_event::<String>().is_none());
+	let Some(ClientEvent::ServerDisconnected(DisconnectReason::Kicked(reason))) = client.handle_event::<String>() else { panic!("expected a kick") };
+	assert_eq!(reason, "bye");
+	assert!(client.send(&"late".to_string(), 0).is_err());
+}
+
+#[test]
+fn client_drop() {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(transport));
+	let client = Client::new(Box::new(connector.connect()));
+
+	let Some(ServerEvent::<String>::NewClient(client_id)) = server.receive_event() else { panic!("expected a new client") };
+	drop(client);
+	let Some(ServerEvent::<String>::ClientDisconnected(id, DisconnectReason::Closed)) = server.receive_event() else { panic!("expected a disconnect") };
+	assert_eq!(id, client_id);
+	assert!(server.send_to(client_id, &"gone".to_string(), 0).is_err());
+}
+
+#[test]
+fn authenticated_clients() {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(transport)).with_authenticator(|_, token: &[u8]| {
+		match token.strip_prefix(b"token:") {
+			Some(name) => Ok(String::from_utf8_lossy(name).into_owned()),
+			None => Err("invalid token".to_string()),
+		}
+	});
+
+	let intruder_transport = connector.connect();
+	let intruder_address = intruder_transport.address();
+	let mut intruder = Client::with_connect_payload(Box::new(intruder_transport), BincodeCodec, "guess").unwrap();
+	let mut client = Client::with_connect_payload(Box::new(connector.connect()), BincodeCodec, "token:alice").unwrap();
+	client.send(&"hello".to_string(), 0).unwrap();
+
+	let Some(ServerEvent::<String>::ClientRejected(address, ConnectionRejectedReason::Rejected(reason))) = server.receive_event() else { panic!("expected a rejection") };
+	assert_eq!((address, reason.as_str()), (intruder_address, "invalid token"));
+	let Some(ServerEvent::<String>::NewClient(client_id)) = server.receive_event() else { panic!("expected a new client") };
+	assert_eq!(server.client_identity(client_id), Some("alice"));
+	let Some(ServerEvent::NewMsg(msg)) = server.receive_event::<String>() else { panic!("expected a msg") };
+	assert_eq!((msg.client_id, msg.msg.as_str()), (client_id, "hello"));
+	assert!(server.receive_event::<String>().is_none());
+	assert_eq!(server.clients().collect::<Vec<_>>(), [client_id]);
+
+	let Some(ClientEvent::<String>::ConnectionRejected(ConnectionRejectedReason::Rejected(reason))) = intruder.handle_event() else { panic!("expected a rejection") };
+	assert_eq!(reason, "invalid token");
+}
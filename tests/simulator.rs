@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use client_server::transport::memory::MemoryServerTransport;
+use client_server::transport::simulator::{ClientSimulatorTransport, Jitter, ManualClock, NetworkConditions, PacketLoss, ServerSimulatorTransport, SimulatorConfig, SimulatorScheduler};
+use client_server::transport::{SendError, ServerTransport, ServerTransportEvent};
+use client_server::{Client, ClientEvent, Server, ServerEvent};
+
+fn conditions(latency_ms: u64) -> NetworkConditions {
+	NetworkConditions {
+		latency: Duration::from_millis(latency_ms),
+		..Default::default()
+	}
+}
+
+fn config(scheduler: &SimulatorScheduler, seed: u64) -> SimulatorConfig {
+	SimulatorConfig {
+		scheduler: scheduler.clone(),
+		seed: Some(seed),
+	}
+}
+
+fn advance(clock: &ManualClock, scheduler: &SimulatorScheduler, millis: u64) {
+	clock.advance(Duration::from_millis(millis));
+	scheduler.run_due_jobs();
+}
+
+fn received_msgs(server: &mut Server) -> Vec<u32> {
+	std::iter::from_fn(|| server.receive_event::<u32>())
+		.filter_map(|event| match event {
+			ServerEvent::NewMsg(msg) => Some(msg.msg),
+			_ => None,
+		})
+		.collect()
+}
+
+#[test]
+fn latency_on_manual_clock() {
+	let clock = ManualClock::new();
+	let scheduler = SimulatorScheduler::with_clock(clock.clone());
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(ServerSimulatorTransport::with_config(conditions(50), transport, config(&scheduler, 0))));
+	let mut client = Client::new(Box::new(ClientSimulatorTransport::with_config(conditions(100), connector.connect(), config(&scheduler, 0))));
+
+	let Some(ServerEvent::<u32>::NewClient(client_id)) = server.receive_event() else { panic!("expected a new client") };
+	client.send(&1u32, 0).unwrap();
+	advance(&clock, &scheduler, 99);
+	assert!(server.receive_event::<u32>().is_none());
+	advance(&clock, &scheduler, 1);
+	assert_eq!(received_msgs(&mut server), [1]);
+
+	server.send_to(client_id, &2u32, 0).unwrap();
+	server.send_to(client_id, &3u32, 0).unwrap();
+	advance(&clock, &scheduler, 49);
+	assert!(client.handle_event::<u32>().is_none());
+	advance(&clock, &scheduler, 1);
+	for expected in [2, 3] {
+		let Some(ClientEvent::MsgFromServer(msg)) = client.handle_event::<u32>() else { panic!("expected a msg") };
+		assert_eq!(msg.msg, expected);
+	}
+}
+
+#[test]
+fn sends_are_ordered_by_due_time() {
+	let clock = ManualClock::new();
+	let scheduler = SimulatorScheduler::with_clock(clock.clone());
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(transport));
+	let mut slow = Client::new(Box::new(ClientSimulatorTransport::with_config(conditions(30), connector.connect(), config(&scheduler, 0))));
+	let mut fast = Client::new(Box::new(ClientSimulatorTransport::with_config(conditions(10), connector.connect(), config(&scheduler, 0))));
+
+	slow.send(&1u32, 0).unwrap();
+	fast.send(&2u32, 0).unwrap();
+	advance(&clock, &scheduler, 100);
+	assert_eq!(received_msgs(&mut server), [2, 1]);
+}
+
+// the msgs that arrived after each millisecond
+fn lossy_session(seed: u64) -> Vec<Vec<u32>> {
+	let clock = ManualClock::new();
+	let scheduler = SimulatorScheduler::with_clock(clock.clone());
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut server = Server::new(Box::new(transport));
+	let conditions = NetworkConditions {
+		latency: Duration::from_millis(20),
+		jitter: Jitter::Normal(Duration::from_millis(10)),
+		loss: PacketLoss::Random(0.3),
+		reorder_percentage: 0.1,
+		duplicate_percentage: 0.1,
+		..Default::default()
+	};
+	let mut client = Client::new(Box::new(ClientSimulatorTransport::with_config(conditions, connector.connect(), config(&scheduler, seed))));
+
+	for i in 0..100u32 {
+		client.send(&i, 0).unwrap();
+	}
+	(0..100).map(|_| {
+		advance(&clock, &scheduler, 1);
+		received_msgs(&mut server)
+	})
+	.collect()
+}
+
+#[test]
+fn same_seed_replays_the_same_session() {
+	let session = lossy_session(42);
+	assert_eq!(session, lossy_session(42));
+	assert_ne!(session, lossy_session(43));
+	let received = session.iter().flatten().count();
+	assert!(received > 0 && received != 100);
+}
+
+#[test]
+fn sends_to_unknown_clients_fail() {
+	let clock = ManualClock::new();
+	let scheduler = SimulatorScheduler::with_clock(clock.clone());
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let mut simulator = ServerSimulatorTransport::with_config(conditions(50), transport, config(&scheduler, 0));
+	let client = connector.connect();
+
+	let Some(ServerTransportEvent::NewClient(address)) = simulator.receive_event() else { panic!("expected a new client") };
+	assert!(simulator.send(address, b"msg", 0).is_ok());
+	let unknown_address = SocketAddr::from(([127, 0, 0, 1], 1));
+	assert!(matches!(simulator.send(unknown_address, b"msg", 0), Err(SendError::Disconnected)));
+
+	drop(client);
+	let Some(ServerTransportEvent::ClientDisconnected(..)) = simulator.receive_event() else { panic!("expected a disconnect") };
+	assert!(matches!(simulator.send(address, b"msg", 0), Err(SendError::Disconnected)));
+}
+
+#[test]
+fn waiting_wakes_up_for_delayed_events() {
+	let transport = MemoryServerTransport::new();
+	let connector = transport.connector();
+	let simulator = ServerSimulatorTransport::new(conditions(50), transport);
+	simulator.handle().set_simulate_incoming(true);
+	let mut server = Server::new(Box::new(simulator));
+	let mut client = Client::new(Box::new(connector.connect()));
+	client.send(&1u32, 0).unwrap();
+
+	let start = Instant::now();
+	let Some(ServerEvent::<u32>::NewClient(_)) = server.wait_event(Duration::from_secs(5)) else { panic!("expected a new client") };
+	let Some(ServerEvent::<u32>::NewMsg(msg)) = server.wait_event(Duration::from_secs(5)) else { panic!("expected a msg") };
+	assert_eq!(msg.msg, 1);
+	assert!(start.elapsed() >= Duration::from_millis(50));
+	assert!(server.wait_event::<u32>(Duration::from_millis(10)).is_none());
+}